@@ -3,7 +3,7 @@
 
 // New threads are spawned using std::thread::spawn function
 
-use std::thread;
+use std::thread::{self, JoinHandle};
 
 // A static variable is owned by the entire program
 static X: [i32; 3] = [2, 4, 6];
@@ -54,6 +54,13 @@ fn changed_main_two() {
     t2.join().unwrap()
 }
 
+// join().unwrap() on each handle stops at the first thread that panicked, and we never hear
+// about the others. join_all joins every handle and keeps each result: the returned value, or the
+// panic payload for threads that panicked.
+pub fn join_all<T>(handles: Vec<JoinHandle<T>>) -> Vec<thread::Result<T>> {
+    handles.into_iter().map(|h| h.join()).collect()
+}
+
 // passing a closure instead of a function
 #[allow(unused)]
 fn changed_main_three() {
@@ -156,3 +163,28 @@ fn f() {
     let id = thread::current().id();
     println!("This is my thread id: {id:?}");
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn join_all_example() {
+        let handles = vec![
+            thread::spawn(|| 1),
+            thread::spawn(|| panic!("second thread failed")),
+            thread::spawn(|| 3),
+        ];
+        let results = join_all(handles);
+
+        assert_eq!(results.len(), 3);
+        assert_eq!(*results[0].as_ref().unwrap(), 1);
+        // the payload of a panic!("...") with a literal message is a &str
+        let payload = results[1].as_ref().unwrap_err();
+        assert_eq!(
+            payload.downcast_ref::<&str>(),
+            Some(&"second thread failed")
+        );
+        assert_eq!(*results[2].as_ref().unwrap(), 3);
+    }
+}
@@ -6,10 +6,11 @@
 
 // A stop flag : Useful to notify a thread to stop working
 
-use std::sync::atomic::Ordering::Relaxed;
-use std::thread;
-#[allow(unused_imports)]
-use std::time::Duration;
+use std::sync::atomic::Ordering::{AcqRel, Acquire, Relaxed, Release};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize};
+use std::sync::Mutex;
+use std::thread::{self, Thread};
+use std::time::{Duration, Instant};
 
 // probably the optimizer will throw this away.
 fn some_work() {
@@ -48,6 +49,33 @@ fn changed_main_one() {
     background_thread.join().unwrap();
 }
 
+// The same stop flag, wrapped up so it doesn't need to be a hand written static every time.
+// stop() is a Release store and should_stop() an Acquire load, so anything the stopping thread did
+// before stopping is visible to a worker once it sees the flag.
+#[derive(Default)]
+pub struct StopFlag(AtomicBool);
+
+impl StopFlag {
+    pub const fn new() -> Self {
+        Self(AtomicBool::new(false))
+    }
+
+    pub fn stop(&self) {
+        self.0.store(true, Release);
+    }
+
+    pub fn should_stop(&self) -> bool {
+        self.0.load(Acquire)
+    }
+
+    // keeps calling work until someone calls stop().
+    pub fn run_until<F: FnMut()>(&self, mut work: F) {
+        while !self.should_stop() {
+            work();
+        }
+    }
+}
+
 // simulating some processing
 fn process_item(_i: usize) {
     // sleep for a sec
@@ -120,41 +148,385 @@ fn changed_main_three() {
 // Progress Reporting using mulitple threads.
 #[allow(unused)]
 fn changed_main_four() {
-    use std::sync::atomic::AtomicUsize;
-
-    let num_done = &AtomicUsize::new(0);
+    let progress = &Progress::new(100);
 
     thread::scope(|s| {
         for t in 0..4 {
             s.spawn(move || {
                 for i in 0..25 {
                     process_item(t * 25 + i);
-                    num_done.fetch_add(1, Relaxed);
+                    progress.item_done();
                 }
             });
+        }
 
-            // main thread displays progress
-            loop {
-                let n = num_done.load(Relaxed);
-                if n == 100 {
-                    break;
-                }
-                println!("Under Progress: {n}/ 100");
+        // main thread displays progress. This has to be outside the spawning loop, otherwise it
+        // would wait for all 100 items while only the first worker is running.
+        loop {
+            let n = progress.done();
+            if n == progress.total() {
+                break;
             }
+            println!("Under Progress: {n}/ 100");
+            progress.wait_until(n + 1);
         }
     });
     println!("Done");
 }
 
+// The atomic counter + park/unpark dance from above, in one place. Only one thread at a time is
+// meant to wait on it.
+pub struct Progress {
+    done: AtomicUsize,
+    total: usize,
+    waiter: Mutex<Option<Thread>>,
+}
+
+impl Progress {
+    pub fn new(total: usize) -> Self {
+        Self {
+            done: AtomicUsize::new(0),
+            total,
+            waiter: Mutex::new(None),
+        }
+    }
+
+    pub fn total(&self) -> usize {
+        self.total
+    }
+
+    pub fn done(&self) -> usize {
+        self.done.load(Acquire)
+    }
+
+    pub fn item_done(&self) {
+        self.done.fetch_add(1, Release);
+        if let Some(t) = &*self.waiter.lock().unwrap() {
+            t.unpark();
+        }
+    }
+
+    // returns once at least n items are done.
+    pub fn wait_until(&self, n: usize) {
+        self.register_waiter();
+        while self.done.load(Acquire) < n {
+            thread::park();
+        }
+        self.unregister_waiter();
+    }
+
+    // Makes item_done unpark the current thread. Register before checking, an item finishing in
+    // between then still unparks us.
+    fn register_waiter(&self) {
+        *self.waiter.lock().unwrap() = Some(thread::current());
+    }
+
+    fn unregister_waiter(&self) {
+        *self.waiter.lock().unwrap() = None;
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum MonitorResult {
+    Completed,
+    TimedOut(usize),
+}
+
+// Like changed_main_three, but gives up at the deadline. Every wake up (an item done, a spurious
+// one, or the timeout) recomputes how long is left instead of parking for a fixed time again.
+pub fn monitor_progress(progress: &Progress, deadline: Instant) -> MonitorResult {
+    progress.register_waiter();
+    let result = loop {
+        let n = progress.done();
+        if n >= progress.total() {
+            break MonitorResult::Completed;
+        }
+        let now = Instant::now();
+        if now >= deadline {
+            break MonitorResult::TimedOut(n);
+        }
+        thread::park_timeout(deadline - now);
+    };
+    progress.unregister_waiter();
+    result
+}
+
+// Lets something through at most once per interval, without a lock, e.g. so a progress line is
+// printed at a capped rate no matter how many threads report progress. Times are kept as micros
+// since `start`, so they fit in an AtomicU64.
+pub struct RateLimiter {
+    start: Instant,
+    // u64::MAX until the first call is allowed.
+    last: AtomicU64,
+    interval_micros: u64,
+}
+
+impl RateLimiter {
+    pub fn new(interval: Duration) -> Self {
+        Self {
+            start: Instant::now(),
+            last: AtomicU64::new(u64::MAX),
+            interval_micros: interval.as_micros() as u64,
+        }
+    }
+
+    pub fn allow(&self) -> bool {
+        let now = self.start.elapsed().as_micros() as u64;
+        let last = self.last.load(Relaxed);
+        // another thread may have stored a `now` taken after ours, hence the saturating_sub.
+        if last != u64::MAX && now.saturating_sub(last) < self.interval_micros {
+            return false;
+        }
+        // several threads can get here for the same interval, only the one that moves `last`
+        // forward wins, the others see it changed and lose.
+        self.last
+            .compare_exchange(last, now, Relaxed, Relaxed)
+            .is_ok()
+    }
+}
+
+// The fan-out from above as a helper: items are split into one chunk per worker, every worker maps
+// its own chunk into its own buffer, and the buffers are glued back together in order.
+pub fn parallel_map<T, R, F>(items: Vec<T>, workers: usize, f: F) -> Vec<R>
+where
+    T: Send,
+    R: Send,
+    F: Fn(T) -> R + Sync,
+{
+    let len = items.len();
+    // with fewer items than workers, some workers just get nothing to do.
+    let chunk_size = len.div_ceil(workers.max(1)).max(1);
+
+    let mut chunks = Vec::new();
+    let mut items = items.into_iter();
+    loop {
+        let chunk: Vec<T> = items.by_ref().take(chunk_size).collect();
+        if chunk.is_empty() {
+            break;
+        }
+        chunks.push(chunk);
+    }
+
+    let f = &f;
+    thread::scope(|s| {
+        let handles: Vec<_> = chunks
+            .into_iter()
+            .map(|chunk| s.spawn(move || chunk.into_iter().map(f).collect::<Vec<R>>()))
+            .collect();
+        let mut results = Vec::with_capacity(len);
+        for h in handles {
+            results.extend(h.join().unwrap());
+        }
+        results
+    })
+}
+
+// Same split, but instead of a value per item every worker folds its chunk into one partial
+// result, starting from its own copy of identity. The partials are combined in chunk order, so
+// reduce has to be associative but not commutative: concatenating still comes out in order.
+pub fn parallel_reduce<T, A, F, R>(items: &[T], identity: A, workers: usize, map: F, reduce: R) -> A
+where
+    T: Sync,
+    A: Clone + Send,
+    F: Fn(A, &T) -> A + Sync,
+    R: Fn(A, A) -> A,
+{
+    if items.is_empty() {
+        return identity;
+    }
+    let chunk_size = items.len().div_ceil(workers.max(1));
+
+    let map = &map;
+    thread::scope(|s| {
+        let handles: Vec<_> = items
+            .chunks(chunk_size)
+            .map(|chunk| {
+                let acc = identity.clone();
+                s.spawn(move || chunk.iter().fold(acc, map))
+            })
+            .collect();
+        handles
+            .into_iter()
+            .map(|h| h.join().unwrap())
+            .reduce(&reduce)
+            .unwrap()
+    })
+}
+
+// Barrier: every thread calling wait() blocks until `total` threads have arrived, then all of
+// them are released together and the barrier can be used again.
+//
+// The generation counter fixes the classic reuse race: a fast thread that leaves and calls wait()
+// again bumps `count` for the *next* round, but the slow threads of the previous round only look
+// at whether the generation they arrived in is over, so they still get out.
+pub struct Barrier {
+    count: AtomicUsize,
+    total: usize,
+    generation: AtomicUsize,
+    waiters: Mutex<Vec<Thread>>,
+}
+
+impl Barrier {
+    pub fn new(n: usize) -> Self {
+        assert!(n > 0, "a barrier needs at least one thread");
+        Self {
+            count: AtomicUsize::new(0),
+            total: n,
+            generation: AtomicUsize::new(0),
+            waiters: Mutex::new(Vec::new()),
+        }
+    }
+
+    pub fn wait(&self) {
+        let generation = self.generation.load(Acquire);
+        // AcqRel: the last thread to arrive sees everything the others did before arriving.
+        if self.count.fetch_add(1, AcqRel) + 1 == self.total {
+            // reset before starting the next generation, so a fast thread coming back sees 0.
+            self.count.store(0, Relaxed);
+            self.generation.fetch_add(1, Release);
+            for t in self.waiters.lock().unwrap().drain(..) {
+                t.unpark();
+            }
+        } else {
+            // if we register after the last thread already drained the list, we just see the new
+            // generation below and leave, the stale entry only causes a harmless spurious unpark.
+            self.waiters.lock().unwrap().push(thread::current());
+            while self.generation.load(Acquire) == generation {
+                thread::park();
+            }
+        }
+    }
+}
+
 // Showing Statistics:
 
-fn main() {
-    use std::sync::atomic::{AtomicU64, AtomicUsize};
-    use std::time::Instant;
+// The counters are updated independently, so a snapshot taken while work is still being recorded
+// can be off by an item or so, but every counter on its own is always correct.
+pub struct Stats {
+    num_done: AtomicUsize,
+    total_time: AtomicU64,
+    max_time: AtomicU64,
+    // exponential moving average in micros, NaN until the first record.
+    moving_average: AtomicF64,
+}
+
+// Weight of the newest sample in the moving average.
+const EMA_ALPHA: f64 = 0.2;
+
+// There is no AtomicF64, but an f64 is just 64 bits, so it can live in an AtomicU64 and be
+// converted with to_bits/from_bits on the way in and out. Arithmetic has to go through a CAS loop,
+// since the processor can't add floats atomically.
+//
+// Every update is atomic, no update is ever lost. The result is still subject to the usual float
+// rounding, and since threads may apply their updates in any order, the last bits can differ
+// between runs. Like the other counters here it uses Relaxed: it's a number, not a signal that
+// other data is ready.
+pub struct AtomicF64 {
+    bits: AtomicU64,
+}
+
+impl AtomicF64 {
+    pub const fn new(v: f64) -> Self {
+        Self {
+            bits: AtomicU64::new(v.to_bits()),
+        }
+    }
+
+    pub fn load(&self) -> f64 {
+        f64::from_bits(self.bits.load(Relaxed))
+    }
 
-    let num_done = &AtomicUsize::new(0);
-    let total_time = &AtomicU64::new(0);
-    let max_time = &AtomicU64::new(0);
+    pub fn store(&self, v: f64) {
+        self.bits.store(v.to_bits(), Relaxed);
+    }
+
+    // applies f atomically and returns the previous value.
+    pub fn fetch_update(&self, mut f: impl FnMut(f64) -> f64) -> f64 {
+        let mut current = self.bits.load(Relaxed);
+        loop {
+            let new = f(f64::from_bits(current)).to_bits();
+            match self
+                .bits
+                .compare_exchange_weak(current, new, Relaxed, Relaxed)
+            {
+                Ok(v) => return f64::from_bits(v),
+                Err(v) => current = v,
+            }
+        }
+    }
+
+    pub fn fetch_add(&self, v: f64) -> f64 {
+        self.fetch_update(|current| current + v)
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct StatsSnapshot {
+    pub count: usize,
+    pub average: Duration,
+    pub peak: Duration,
+}
+
+impl Stats {
+    pub const fn new() -> Self {
+        Self {
+            num_done: AtomicUsize::new(0),
+            total_time: AtomicU64::new(0),
+            max_time: AtomicU64::new(0),
+            moving_average: AtomicF64::new(f64::NAN),
+        }
+    }
+
+    pub fn record(&self, micros: u64) {
+        self.total_time.fetch_add(micros, Relaxed);
+        self.max_time.fetch_max(micros, Relaxed);
+        let x = micros as f64;
+        self.moving_average.fetch_update(|avg| {
+            if avg.is_nan() {
+                x
+            } else {
+                avg + EMA_ALPHA * (x - avg)
+            }
+        });
+        self.num_done.fetch_add(1, Relaxed);
+    }
+
+    // unlike the average in the snapshot, recent items count more than old ones.
+    pub fn moving_average(&self) -> f64 {
+        let avg = self.moving_average.load();
+        if avg.is_nan() {
+            0.0
+        } else {
+            avg
+        }
+    }
+
+    pub fn snapshot(&self) -> StatsSnapshot {
+        let count = self.num_done.load(Relaxed);
+        let total_time = self.total_time.load(Relaxed);
+        let peak = Duration::from_micros(self.max_time.load(Relaxed));
+        let average = if count == 0 {
+            Duration::ZERO
+        } else {
+            Duration::from_micros(total_time / count as u64)
+        };
+        StatsSnapshot {
+            count,
+            average,
+            peak,
+        }
+    }
+}
+
+// not derived: the moving average has to start out as NaN, not 0.
+impl Default for Stats {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn main() {
+    let stats = &Stats::new();
 
     thread::scope(|s| {
         for t in 0..4 {
@@ -162,18 +534,14 @@ fn main() {
                 for i in 0..25 {
                     let start = Instant::now();
                     process_item(t * 25 + i);
-                    let time_taken = start.elapsed().as_micros() as u64;
-                    num_done.fetch_add(1, Relaxed);
-                    total_time.fetch_add(1, Relaxed);
-                    max_time.fetch_max(time_taken, Relaxed);
+                    stats.record(start.elapsed().as_micros() as u64);
                 }
             });
         }
         // main thread displays the statistcs of the progress.
         loop {
-            let total_time = Duration::from_micros(total_time.load(Relaxed));
-            let max_time = Duration::from_micros(max_time.load(Relaxed));
-            let n = num_done.load(Relaxed);
+            let snapshot = stats.snapshot();
+            let n = snapshot.count;
 
             if n == 100 {
                 break;
@@ -183,8 +551,7 @@ fn main() {
             } else {
                 println!(
                     "Progress.. {n}/100 done, {:?} average, {:?} peak",
-                    total_time / n as u32,
-                    max_time
+                    snapshot.average, snapshot.peak
                 );
             }
             thread::sleep(Duration::from_secs(1));
@@ -192,3 +559,231 @@ fn main() {
     });
     println!("Done");
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn barrier_example() {
+        use std::time::Instant;
+
+        let barrier = Barrier::new(4);
+        let arrivals = Mutex::new(Vec::new());
+        let releases = Mutex::new(Vec::new());
+
+        thread::scope(|s| {
+            for t in 0..4 {
+                let (barrier, arrivals, releases) = (&barrier, &arrivals, &releases);
+                s.spawn(move || {
+                    // a few rounds to make sure the barrier can be reused.
+                    for round in 0..3 {
+                        thread::sleep(Duration::from_millis(10 * t));
+                        arrivals.lock().unwrap().push((round, Instant::now()));
+                        barrier.wait();
+                        releases.lock().unwrap().push((round, Instant::now()));
+                    }
+                });
+            }
+        });
+
+        let arrivals = arrivals.into_inner().unwrap();
+        let releases = releases.into_inner().unwrap();
+        for (round, released) in &releases {
+            for (_, arrived) in arrivals.iter().filter(|(r, _)| r == round) {
+                assert!(arrived <= released);
+            }
+        }
+    }
+
+    #[test]
+    fn stats_example() {
+        let stats = Stats::new();
+        assert_eq!(stats.snapshot().count, 0);
+        assert_eq!(stats.snapshot().average, Duration::ZERO);
+
+        thread::scope(|s| {
+            for t in 1..=4 {
+                let stats = &stats;
+                // thread t records t*10, t*20, t*30 micros
+                s.spawn(move || {
+                    for i in 1..=3 {
+                        stats.record(t * i * 10);
+                    }
+                });
+            }
+        });
+
+        // (10 + 20 + 30 + 40) * (1 + 2 + 3) / 12 = 50
+        assert_eq!(
+            stats.snapshot(),
+            StatsSnapshot {
+                count: 12,
+                average: Duration::from_micros(50),
+                peak: Duration::from_micros(120),
+            }
+        );
+    }
+
+    #[test]
+    fn progress_example() {
+        let progress = Progress::new(100);
+        thread::scope(|s| {
+            s.spawn(|| {
+                for _ in 0..100 {
+                    thread::sleep(Duration::from_micros(100));
+                    progress.item_done();
+                }
+            });
+            progress.wait_until(100);
+            assert_eq!(progress.done(), 100);
+        });
+    }
+
+    #[test]
+    fn stop_flag_example() {
+        let stop = StopFlag::new();
+        let counter = AtomicUsize::new(0);
+
+        thread::scope(|s| {
+            let worker = s.spawn(|| {
+                stop.run_until(|| {
+                    counter.fetch_add(1, Relaxed);
+                })
+            });
+            while counter.load(Relaxed) < 1000 {
+                thread::yield_now();
+            }
+            stop.stop();
+            worker.join().unwrap();
+        });
+
+        // the worker is gone, the counter doesn't move anymore.
+        let n = counter.load(Relaxed);
+        thread::sleep(Duration::from_millis(10));
+        assert_eq!(counter.load(Relaxed), n);
+        assert!(n >= 1000);
+    }
+
+    #[test]
+    fn parallel_map_example() {
+        let serial: Vec<u64> = (0..100).map(|i| i * i).collect();
+        assert_eq!(parallel_map((0..100).collect(), 4, |i: u64| i * i), serial);
+        // uneven split, no workers, more workers than items
+        assert_eq!(parallel_map((0..100).collect(), 7, |i: u64| i * i), serial);
+        assert_eq!(parallel_map(vec![1, 2, 3], 0, |i| i + 1), [2, 3, 4]);
+        assert_eq!(parallel_map(vec![1, 2, 3], 16, |i| i + 1), [2, 3, 4]);
+        assert!(parallel_map(Vec::<i32>::new(), 4, |i| i).is_empty());
+    }
+
+    #[test]
+    fn monitor_progress_example() {
+        let progress = Progress::new(20);
+        thread::scope(|s| {
+            s.spawn(|| {
+                for _ in 0..20 {
+                    thread::sleep(Duration::from_millis(20));
+                    progress.item_done();
+                }
+            });
+
+            let deadline = Instant::now() + Duration::from_millis(100);
+            match monitor_progress(&progress, deadline) {
+                MonitorResult::TimedOut(n) => assert!(n < 20),
+                MonitorResult::Completed => panic!("the worker can't be done yet"),
+            }
+            let deadline = Instant::now() + Duration::from_secs(10);
+            assert_eq!(
+                monitor_progress(&progress, deadline),
+                MonitorResult::Completed
+            );
+        });
+    }
+
+    #[test]
+    fn moving_average_example() {
+        let sum = AtomicF64::new(0.0);
+        thread::scope(|s| {
+            for _ in 0..4 {
+                s.spawn(|| {
+                    for _ in 0..1000 {
+                        sum.fetch_add(0.5);
+                    }
+                });
+            }
+        });
+        assert_eq!(sum.load(), 2000.0);
+
+        let stats = Stats::new();
+        assert_eq!(stats.moving_average(), 0.0);
+        // whatever order the threads record in, after a phase of only 50s the average is at 50, and
+        // after a phase of only 200s it has moved over to 200.
+        for micros in [50, 200] {
+            thread::scope(|s| {
+                for _ in 0..4 {
+                    s.spawn(|| {
+                        for _ in 0..100 {
+                            stats.record(micros);
+                        }
+                    });
+                }
+            });
+            assert!((stats.moving_average() - micros as f64).abs() < 0.01);
+        }
+    }
+
+    #[test]
+    fn rate_limiter_example() {
+        let limiter = RateLimiter::new(Duration::from_millis(20));
+        let allowed = AtomicUsize::new(0);
+        let start = Instant::now();
+        thread::scope(|s| {
+            for _ in 0..4 {
+                s.spawn(|| {
+                    while start.elapsed() < Duration::from_millis(200) {
+                        if limiter.allow() {
+                            allowed.fetch_add(1, Relaxed);
+                        }
+                    }
+                });
+            }
+        });
+        let allowed = allowed.into_inner();
+        // 200ms / 20ms, plus the very first call.
+        assert!((1..=11).contains(&allowed), "{allowed} allowed");
+    }
+
+    #[test]
+    fn parallel_reduce_example() {
+        let numbers: Vec<u64> = (0..1000).collect();
+        let sum = parallel_reduce(&numbers, 0, 4, |acc, &n| acc + n, |a, b| a + b);
+        assert_eq!(sum, numbers.iter().sum());
+        // uneven split, more workers than items, nothing to do.
+        assert_eq!(
+            parallel_reduce(&numbers, 0, 7, |acc, &n| acc + n, |a, b| a + b),
+            sum
+        );
+        assert_eq!(
+            parallel_reduce(&[1, 2], 0, 16, |acc, &n| acc + n, |a, b| a + b),
+            3
+        );
+        assert_eq!(
+            parallel_reduce(&[] as &[u64], 42, 4, |acc, &n| acc + n, |a, b| a + b),
+            42
+        );
+
+        // however the threads are scheduled, the pieces are put together in order.
+        let words: Vec<String> = (0..100).map(|i| i.to_string()).collect();
+        let joined = parallel_reduce(
+            &words,
+            String::new(),
+            4,
+            |mut acc, w| {
+                acc.push_str(w);
+                acc
+            },
+            |a, b| a + &b,
+        );
+        assert_eq!(joined, words.concat());
+    }
+}
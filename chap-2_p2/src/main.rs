@@ -48,10 +48,8 @@ fn allocate_new_id() -> u32 {
 ///     }
 ///  }
 /// ```
-///
-
+//
 // Incrementing a AtomicU32 without using fetch_add.
-
 #[allow(unused)]
 fn increment(a: &AtomicU32) {
     let mut current = a.load(Relaxed);
@@ -66,6 +64,32 @@ fn increment(a: &AtomicU32) {
     }
 }
 
+// The same loop, but for any read-modify-write operation instead of just adding one. f may be
+// called more than once when other threads get in between, so it shouldn't have side effects
+// that matter.
+pub fn atomic_update(a: &AtomicU32, f: impl FnMut(u32) -> u32) {
+    atomic_update_counted(a, f);
+}
+
+// Also reports how many times the compare exchange failed before it went through, i.e. how much
+// work was thrown away. If that's high most of the time, a lock might do better. Spurious
+// failures of the weak version count as well, they cost a retry just the same.
+pub fn atomic_update_counted(a: &AtomicU32, mut f: impl FnMut(u32) -> u32) -> (u32, u32) {
+    let mut current = a.load(Relaxed);
+    let mut retries = 0;
+    loop {
+        let new = f(current);
+        // the weak version may fail spuriously, that's fine since we loop anyway.
+        match a.compare_exchange_weak(current, new, Relaxed, Relaxed) {
+            Ok(_) => return (new, retries),
+            Err(v) => {
+                current = v;
+                retries += 1;
+            }
+        }
+    }
+}
+
 #[allow(unused)]
 fn allocate_new_id_updated() -> u32 {
     static NEW_ID: AtomicU32 = AtomicU32::new(0);
@@ -80,6 +104,38 @@ fn allocate_new_id_updated() -> u32 {
     }
 }
 
+// The same allocator without any panics: at the limit it returns None and leaves the counter
+// alone, so it can never creep up towards an overflow like allocate_new_id does.
+pub struct IdAllocator {
+    next: AtomicU32,
+    max: u32,
+}
+
+impl IdAllocator {
+    pub const fn new(max: u32) -> Self {
+        Self {
+            next: AtomicU32::new(0),
+            max,
+        }
+    }
+
+    pub fn checked_allocate(&self) -> Option<u32> {
+        let mut id = self.next.load(Relaxed);
+        loop {
+            if id >= self.max {
+                return None;
+            }
+            match self
+                .next
+                .compare_exchange_weak(id, id + 1, Relaxed, Relaxed)
+            {
+                Ok(_) => return Some(id),
+                Err(v) => id = v,
+            }
+        }
+    }
+}
+
 // this function panics
 fn main() {
     use std::thread;
@@ -113,7 +169,7 @@ fn get_key() -> u32 {
     if id == 0 {
         let new_value: u32 = id + 1; // could be any random number
         match KEY.compare_exchange_weak(id, new_value, Relaxed, Relaxed) {
-            Ok(_) => return new_value,
+            Ok(_) => new_value,
             // Some other thread has already generated the key, so just return the value.
             Err(k) => k,
         }
@@ -121,3 +177,99 @@ fn get_key() -> u32 {
         id
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn atomic_update_example() {
+        use std::thread;
+
+        let doubled = AtomicU32::new(1);
+        thread::scope(|s| {
+            for _ in 0..10 {
+                s.spawn(|| atomic_update(&doubled, |v| v * 2));
+            }
+        });
+        assert_eq!(doubled.load(Relaxed), 1 << 10);
+
+        // 8 * 20 = 160 subtractions of 1 from 100 has to stop at 0 instead of wrapping.
+        let remaining = AtomicU32::new(100);
+        thread::scope(|s| {
+            for _ in 0..8 {
+                s.spawn(|| {
+                    for _ in 0..20 {
+                        atomic_update(&remaining, |v| v.saturating_sub(1));
+                    }
+                });
+            }
+        });
+        assert_eq!(remaining.load(Relaxed), 0);
+    }
+
+    #[test]
+    fn checked_allocate_example() {
+        use std::sync::Mutex;
+        use std::thread;
+
+        let allocator = IdAllocator::new(1000);
+        let ids = Mutex::new(Vec::new());
+        thread::scope(|s| {
+            for _ in 0..8 {
+                s.spawn(|| {
+                    for _ in 0..1000 {
+                        if let Some(id) = allocator.checked_allocate() {
+                            ids.lock().unwrap().push(id);
+                        }
+                    }
+                });
+            }
+        });
+
+        assert_eq!(allocator.next.load(Relaxed), 1000);
+        let mut ids = ids.into_inner().unwrap();
+        ids.sort();
+        // every id handed out exactly once
+        assert_eq!(ids, (0..1000).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn atomic_update_counted_example() {
+        use std::thread;
+
+        // nobody else around, so the first attempt succeeds. Only on x86 though: on LL/SC targets
+        // like ARM the weak exchange can fail spuriously, and then it's a retry all the same.
+        let a = AtomicU32::new(5);
+        let (new, retries) = atomic_update_counted(&a, |v| v + 1);
+        assert_eq!(new, 6);
+        if cfg!(any(target_arch = "x86", target_arch = "x86_64")) {
+            assert_eq!(retries, 0);
+        }
+
+        let a = AtomicU32::new(0);
+        let retries: u32 = thread::scope(|s| {
+            let handles: Vec<_> = (0..8)
+                .map(|_| {
+                    s.spawn(|| {
+                        (0..1000)
+                            .map(|_| {
+                                atomic_update_counted(&a, |v| {
+                                    // gives the others a chance to get in between, even on one core.
+                                    thread::yield_now();
+                                    v + 1
+                                })
+                                .1
+                            })
+                            .sum::<u32>()
+                    })
+                })
+                .collect();
+            handles.into_iter().map(|h| h.join().unwrap()).sum()
+        });
+        assert_eq!(a.load(Relaxed), 8000);
+        // how many depends on the machine and the scheduler, but with 8 threads hammering one atomic
+        // some updates have to be redone.
+        assert!(retries > 0);
+    }
+}
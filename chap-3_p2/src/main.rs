@@ -2,9 +2,12 @@
 
 //Mutex are most common use case for release and acquire ordering
 
-use std::sync::atomic::AtomicBool;
-use std::sync::atomic::Ordering::{Acquire, Relaxed, Release};
-use std::thread;
+use std::marker::PhantomData;
+use std::ops::Deref;
+use std::sync::atomic::Ordering::{self, Acquire, Relaxed, Release};
+use std::sync::atomic::{AtomicBool, AtomicPtr, AtomicU32, AtomicUsize};
+use std::sync::Mutex;
+use std::thread::{self, Thread};
 static mut DATA: String = String::new();
 static LOCKED: AtomicBool = AtomicBool::new(false);
 
@@ -17,11 +20,12 @@ fn f() {
         .is_ok()
     {
         //Safety: We hold the lock, so nothing else is accessing DATA
-        unsafe { DATA.push('!') };
+        unsafe { (*std::ptr::addr_of_mut!(DATA)).push('!') };
         LOCKED.store(false, Release);
     }
 }
 
+#[allow(unused)]
 fn changed_main() {
     thread::scope(|s| {
         for _ in 0..100 {
@@ -29,55 +33,298 @@ fn changed_main() {
         }
     });
 }
-///```rust
-///use std::sync::atomic::AtomicPtr;
-///
-///fn get_data() -> &'static Data {
-/// static PTR: AtomicPtr = AtomicPtr::new(std::ptr::null_mut());
-///
-/// let mut p = PTR.load(Acquire);
-///
-/// if p.is_null(){
-///  p = Box::into_raw(Box::new(generate_data()));
-///  if let Err(e) = PTR.compare_exchange(std::ptr::null_mut(), p , Release, Acquire){
-///    // Safety: p is not shared with any other thread
-///    drop(unsafe{ Box::from_raw(p)});
-///    p = e;
-///  }
-/// }
-/// // Safety: p is not null and points to a properly initialize value
-/// unsafe { &*p }
-///
-///}
+//```rust
+//use std::sync::atomic::AtomicPtr;
+//
+//fn get_data() -> &'static Data {
+// static PTR: AtomicPtr = AtomicPtr::new(std::ptr::null_mut());
+//
+// let mut p = PTR.load(Acquire);
+//
+// if p.is_null(){
+//  p = Box::into_raw(Box::new(generate_data()));
+//  if let Err(e) = PTR.compare_exchange(std::ptr::null_mut(), p , Release, Acquire){
+//    // Safety: p is not shared with any other thread
+//    drop(unsafe{ Box::from_raw(p)});
+//    p = e;
+//  }
+// }
+// // Safety: p is not null and points to a properly initialize value
+// unsafe { &*p }
+//
+//}
 // We need to use `Acquire` for both the load memory ordering and `compare_exchange`
 // failure memoring ordering to be able to synchronize with the operation that stores
 // the pointer. This store happens when the compare_exchange succeeds, so we must use
 // Release as its success ordering.
 
+// Lazy: the same AtomicPtr idea, but holding on to its own initializer and making sure it runs
+// only once. Instead of letting every thread build a value and throwing away the losers, one
+// thread is elected through `initializing` and the others wait for the pointer to show up.
+//
+// The initializer must not use the Lazy it's initializing: that thread would wait for itself
+// forever.
+pub struct Lazy<T, F = fn() -> T> {
+    ptr: AtomicPtr<T>,
+    initializing: AtomicBool,
+    init: F,
+    // we own a T, so we're only Send if T is.
+    _owns: PhantomData<T>,
+}
+
+// any thread can run init and get a &T, and the value may be dropped by another thread.
+unsafe impl<T: Send + Sync, F: Sync> Sync for Lazy<T, F> {}
+
+impl<T, F: Fn() -> T> Lazy<T, F> {
+    pub const fn new(init: F) -> Self {
+        Self {
+            ptr: AtomicPtr::new(std::ptr::null_mut()),
+            initializing: AtomicBool::new(false),
+            init,
+            _owns: PhantomData,
+        }
+    }
+
+    pub fn get(&self) -> &T {
+        loop {
+            let p = self.ptr.load(Acquire);
+            if !p.is_null() {
+                // Safety: p is not null and points to a properly initialize value
+                return unsafe { &*p };
+            }
+            if self
+                .initializing
+                .compare_exchange(false, true, Acquire, Relaxed)
+                .is_ok()
+            {
+                // hands the job to the next thread if init panics, and is harmless otherwise.
+                let _reset = ResetOnDrop(&self.initializing);
+                // someone might have finished right before we won the race.
+                if self.ptr.load(Acquire).is_null() {
+                    let p = Box::into_raw(Box::new((self.init)()));
+                    self.ptr.store(p, Release);
+                }
+            } else {
+                thread::yield_now();
+            }
+        }
+    }
+
+    pub fn force(&self) {
+        self.get();
+    }
+}
+
+struct ResetOnDrop<'a>(&'a AtomicBool);
+
+impl Drop for ResetOnDrop<'_> {
+    fn drop(&mut self) {
+        self.0.store(false, Release);
+    }
+}
+
+impl<T, F: Fn() -> T> Deref for Lazy<T, F> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        self.get()
+    }
+}
+
+impl<T, F> Drop for Lazy<T, F> {
+    fn drop(&mut self) {
+        let p = *self.ptr.get_mut();
+        if !p.is_null() {
+            // Safety: p came from Box::into_raw and nobody else can use it anymore.
+            drop(unsafe { Box::from_raw(p) });
+        }
+    }
+}
+
+// Once: runs a closure exactly once, no matter how many threads call call_once at the same time.
+// One thread wins the INCOMPLETE -> RUNNING exchange and runs it, the others park until it's done.
+// If the closure panics there's no telling what state it left things in, so the Once becomes
+// poisoned and every later call_once panics instead of pretending it ran.
+const INCOMPLETE: u32 = 0;
+const RUNNING: u32 = 1;
+const COMPLETE: u32 = 2;
+const POISONED: u32 = 3;
+
+pub struct Once {
+    state: AtomicU32,
+    waiters: Mutex<Vec<Thread>>,
+}
+
+impl Once {
+    pub const fn new() -> Self {
+        Self {
+            state: AtomicU32::new(INCOMPLETE),
+            waiters: Mutex::new(Vec::new()),
+        }
+    }
+
+    pub fn call_once(&self, f: impl FnOnce()) {
+        loop {
+            match self
+                .state
+                .compare_exchange(INCOMPLETE, RUNNING, Acquire, Acquire)
+            {
+                Ok(_) => {
+                    // if f panics, this is dropped while unwinding and poisons the Once.
+                    let mut finish = Finish {
+                        once: self,
+                        state: POISONED,
+                    };
+                    f();
+                    finish.state = COMPLETE;
+                    return;
+                }
+                Err(COMPLETE) => return,
+                Err(POISONED) => panic!("Once instance has previously been poisoned"),
+                Err(_) => {
+                    // register before checking, so finishing in between still unparks us.
+                    self.waiters.lock().unwrap().push(thread::current());
+                    while self.state.load(Acquire) == RUNNING {
+                        thread::park();
+                    }
+                }
+            }
+        }
+    }
+
+    pub fn is_completed(&self) -> bool {
+        self.state.load(Acquire) == COMPLETE
+    }
+}
+
+impl Default for Once {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+struct Finish<'a> {
+    once: &'a Once,
+    state: u32,
+}
+
+impl Drop for Finish<'_> {
+    fn drop(&mut self) {
+        self.once.state.store(self.state, Release);
+        for t in self.once.waiters.lock().unwrap().drain(..) {
+            t.unpark();
+        }
+    }
+}
+
 // Sequentially Consistent Ordering
 // It includes all the guarantees of acquire ordering and release ordering
 use std::sync::atomic::Ordering::SeqCst;
 
-static A: AtomicBool = AtomicBool::new(false);
-static B: AtomicBool = AtomicBool::new(false);
+// Both threads first store their own flag, then check the other one. With SeqCst every thread
+// agrees on a single order of those four operations. The last one is always a load, and it sees
+// the other flag already set, so at most one thread can push. Neither pushing is fine though,
+// that happens when both stores come before both loads.
+//
+// Running it with Relaxed instead drops that guarantee, and on some hardware both threads may
+// push. Only Relaxed and SeqCst are valid for both a load and a store.
+pub fn run_store_buffer(ordering: Ordering) -> usize {
+    assert!(
+        matches!(ordering, Relaxed | SeqCst),
+        "ordering must be Relaxed or SeqCst"
+    );
+    // fresh flags every run, statics would remember the previous run.
+    let a = AtomicBool::new(false);
+    let b = AtomicBool::new(false);
+    let pushed = AtomicUsize::new(0);
+
+    thread::scope(|s| {
+        s.spawn(|| {
+            a.store(true, ordering);
+            if !b.load(ordering) {
+                pushed.fetch_add(1, Relaxed);
+            }
+        });
+        s.spawn(|| {
+            b.store(true, ordering);
+            if !a.load(ordering) {
+                pushed.fetch_add(1, Relaxed);
+            }
+        });
+    });
 
-static mut S: String = String::new();
+    pushed.into_inner()
+}
 
 fn main() {
-    let a = thread::spawn(|| {
-        A.store(true, SeqCst);
-        if !B.load(SeqCst) {
-            unsafe { S.push('!') };
-        }
-    });
+    let pushed = run_store_buffer(SeqCst);
+    println!("pushed {pushed} time(s)");
+}
 
-    let b = thread::spawn(|| {
-        B.store(true, SeqCst);
-        if !A.load(SeqCst) {
-            unsafe { S.push('!') };
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn store_buffer_example() {
+        for _ in 0..1000 {
+            assert!(run_store_buffer(SeqCst) <= 1);
         }
-    });
+    }
+
+    // No such promise with Relaxed, so there's nothing to check. This is only for looking at what
+    // happens: cargo test -- --ignored --nocapture store_buffer_relaxed
+    #[test]
+    #[ignore]
+    fn store_buffer_relaxed() {
+        let both = (0..1000).filter(|_| run_store_buffer(Relaxed) == 2).count();
+        println!("Relaxed: both threads pushed in {both} of 1000 runs");
+    }
+
+    #[test]
+    fn lazy_example() {
+        static BUILDS: AtomicUsize = AtomicUsize::new(0);
+        static SQUARES: Lazy<Vec<u64>> = Lazy::new(|| {
+            BUILDS.fetch_add(1, Relaxed);
+            thread::sleep(std::time::Duration::from_millis(50));
+            (0..1000).map(|i| i * i).collect()
+        });
+
+        thread::scope(|s| {
+            for _ in 0..8 {
+                s.spawn(|| assert_eq!(SQUARES[12], 144));
+            }
+        });
+        SQUARES.force();
+        assert_eq!(SQUARES.len(), 1000);
+        assert_eq!(BUILDS.load(Relaxed), 1);
+    }
 
-    a.join().unwrap();
-    b.join().unwrap();
+    #[test]
+    fn once_example() {
+        use std::panic::{self, AssertUnwindSafe};
+
+        static ONCE: Once = Once::new();
+        static RUNS: AtomicUsize = AtomicUsize::new(0);
+
+        thread::scope(|s| {
+            for _ in 0..16 {
+                s.spawn(|| {
+                    ONCE.call_once(|| {
+                        thread::sleep(std::time::Duration::from_millis(20));
+                        RUNS.fetch_add(1, Relaxed);
+                    });
+                    // whoever ran it, it's done by the time call_once returns.
+                    assert!(ONCE.is_completed());
+                    assert_eq!(RUNS.load(Relaxed), 1);
+                });
+            }
+        });
+
+        let poisoned = Once::new();
+        let r = panic::catch_unwind(AssertUnwindSafe(|| poisoned.call_once(|| panic!("oops"))));
+        assert!(r.is_err());
+        let r = panic::catch_unwind(AssertUnwindSafe(|| poisoned.call_once(|| {})));
+        assert!(r.is_err());
+    }
 }
@@ -1,46 +1,23 @@
 // Channels can be used to send data between threads.
 //
-// Mutex Based Channels
-//
-// Use a VecDeque,
-// send pushes  item to it
-// receive pops item from it
-
-/*
-use std::collections::VecDeque;
-use std::sync::{Condvar, Mutex};
-
-pub struct Channel<T> {
-    queue: Mutex<VecDeque<T>>,
-    item_ready: Condvar,
-}
-
-impl<T> Channel<T> {
-    pub fn new() -> Self {
-        Self {
-            queue: Mutex::new(VecDeque::new()),
-            item_ready: Condvar::new(),
-        }
-    }
+// Mutex Based Channels: see mutex_channel.rs
+// Multi-producer, single consumer: see mpsc.rs
+// Latest value wins: see replacing.rs
+// Bounded SPSC ring buffer: see ring.rs
 
-    pub fn send(&self, message: T) {
-        self.queue.lock().unwrap().push_back(message);
-        //notify to any one waiting thread
-        self.item_ready.notify_one();
-    }
+// main only uses part of it, the tests cover the rest.
+#[allow(unused)]
+mod mutex_channel;
+// only used by their tests.
+#[allow(unused)]
+mod mpsc;
+#[allow(unused)]
+mod pool;
+#[allow(unused)]
+mod replacing;
+#[allow(unused)]
+mod ring;
 
-    pub fn receive(&self) -> T {
-        let mut val = self.queue.lock().unwrap();
-        loop {
-            if let Some(v) = val.pop_front() {
-                return v;
-            }
-            //block the thread until you receive a notification
-            val = self.item_ready.wait(val).unwrap();
-        }
-    }
-}
-*/
 // One-Shot Channel: Sending exactly one msg frm one thread to another.
 //
 
@@ -75,18 +52,35 @@ impl<T> Channel<T> {
     }
 
     pub fn send(&self, value: T) {
-        if self.in_use.swap(true, Relaxed) {
+        if self.try_send(value).is_err() {
             panic!("Can't send more than one message");
         }
+    }
+
+    // like send, but a second message is handed back instead of panicking.
+    pub fn try_send(&self, value: T) -> Result<(), T> {
+        if self.in_use.swap(true, Relaxed) {
+            return Err(value);
+        }
 
         unsafe { (*self.message.get()).write(value) };
         self.ready.store(true, Release);
+        Ok(())
     }
 
+    // Relaxed is fine for polling when a recieve follows, its swap is Acquire and does the
+    // synchronizing. A true result on its own doesn't make the message (or anything else the
+    // sender did before sending) visible though, for that use is_ready_acquire.
     pub fn is_ready(&self) -> bool {
         self.ready.load(Relaxed)
     }
 
+    // Acquire pairs with the Release store in send: once this returns true, everything the
+    // sender did before sending is visible to us.
+    pub fn is_ready_acquire(&self) -> bool {
+        self.ready.load(Acquire)
+    }
+
     pub fn recieve(&self) -> T {
         if !self.ready.swap(false, Acquire) {
             panic!("No Message Available");
@@ -94,6 +88,34 @@ impl<T> Channel<T> {
         // Safety: We already reset the ready flag
         unsafe { (*self.message.get()).assume_init_read() }
     }
+
+    /// Look at the message without taking it, the ready flag stays set.
+    ///
+    /// # Safety
+    ///
+    /// `recieve` moves the message out, so it must not be called while `peek` is running on
+    /// another thread, nor while a reference returned by `peek` is still in use.
+    pub unsafe fn peek(&self) -> Option<&T> {
+        // Acquire, so the message is fully written.
+        if self.is_ready_acquire() {
+            Some(unsafe { (*self.message.get()).assume_init_ref() })
+        } else {
+            None
+        }
+    }
+
+    // A channel is single use: in_use stays set after recieve, so a second send panics. With
+    // exclusive access there can't be a send or recieve going on, so both flags can be reset
+    // directly, no atomic operations (or orderings) needed. A message nobody received is dropped
+    // here instead of being leaked or overwritten.
+    pub fn reuse(&mut self) {
+        if *self.ready.get_mut() {
+            // Safety: ready means the message was written and never read.
+            unsafe { self.message.get_mut().assume_init_drop() }
+        }
+        *self.ready.get_mut() = false;
+        *self.in_use.get_mut() = false;
+    }
 }
 
 fn main() {
@@ -112,3 +134,133 @@ fn main() {
         assert_eq!(chan.recieve(), "Hello, World");
     });
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn peek_example() {
+        let chan = Channel::new();
+        // Safety: nothing is receiving while we peek.
+        assert_eq!(unsafe { chan.peek() }, None);
+
+        chan.send(String::from("peek-a-boo"));
+        assert_eq!(
+            unsafe { chan.peek() }.map(String::as_str),
+            Some("peek-a-boo")
+        );
+        assert_eq!(
+            unsafe { chan.peek() }.map(String::as_str),
+            Some("peek-a-boo")
+        );
+
+        assert_eq!(chan.recieve(), "peek-a-boo");
+        assert_eq!(unsafe { chan.peek() }, None);
+    }
+
+    #[test]
+    fn try_send_example() {
+        let chan = Channel::new();
+        let (a, b) = thread::scope(|s| {
+            let a = s.spawn(|| chan.try_send(String::from("a")));
+            let b = s.spawn(|| chan.try_send(String::from("b")));
+            (a.join().unwrap(), b.join().unwrap())
+        });
+        let sent = match (a, b) {
+            (Ok(()), Err(back)) => {
+                assert_eq!(back, "b");
+                "a"
+            }
+            (Err(back), Ok(())) => {
+                assert_eq!(back, "a");
+                "b"
+            }
+            _ => panic!("exactly one try_send should succeed"),
+        };
+        assert_eq!(chan.recieve(), sent);
+    }
+
+    // The sender sets `side` right before sending. Seeing the channel ready through
+    // is_ready_acquire guarantees we also see that store. With is_ready (Relaxed) there's no such
+    // guarantee: x86 happens to never show a stale value, but weaker hardware like ARM can.
+    #[test]
+    fn is_ready_acquire_example() {
+        use std::sync::atomic::AtomicUsize;
+
+        for i in 1..=1000 {
+            let chan = Channel::new();
+            let side = AtomicUsize::new(0);
+            thread::scope(|s| {
+                s.spawn(|| {
+                    side.store(i, Relaxed);
+                    chan.send(i);
+                });
+                while !chan.is_ready_acquire() {
+                    thread::yield_now();
+                }
+                assert_eq!(side.load(Relaxed), i);
+                assert_eq!(chan.recieve(), i);
+            });
+        }
+    }
+
+    // The same thing through is_ready. Nothing guarantees we see the store to `side` here, so all
+    // this can do is count how often we didn't. On x86 that's always zero. Only for looking at:
+    // cargo test -- --ignored --nocapture is_ready_relaxed
+    #[test]
+    #[ignore]
+    fn is_ready_relaxed_example() {
+        use std::sync::atomic::AtomicUsize;
+
+        let mut stale = 0;
+        for i in 1..=1000 {
+            let chan = Channel::new();
+            let side = AtomicUsize::new(0);
+            thread::scope(|s| {
+                s.spawn(|| {
+                    side.store(i, Relaxed);
+                    chan.send(i);
+                });
+                while !chan.is_ready() {
+                    thread::yield_now();
+                }
+                if side.load(Relaxed) != i {
+                    stale += 1;
+                }
+                assert_eq!(chan.recieve(), i);
+            });
+        }
+        println!("is_ready saw a stale value {stale} times out of 1000");
+    }
+
+    #[test]
+    fn reuse_example() {
+        use std::panic::{self, AssertUnwindSafe};
+        use std::rc::Rc;
+
+        let mut chan = Channel::new();
+        chan.send(1);
+        assert_eq!(chan.recieve(), 1);
+        // still single use, even after receiving.
+        let r = panic::catch_unwind(AssertUnwindSafe(|| chan.send(2)));
+        assert!(r.is_err());
+
+        chan.reuse();
+        thread::scope(|s| {
+            s.spawn(|| chan.send(3));
+        });
+        assert_eq!(chan.recieve(), 3);
+
+        // reusing with a message still in there drops it.
+        let mut chan = Channel::new();
+        let counted = Rc::new(());
+        chan.send(counted.clone());
+        assert_eq!(Rc::strong_count(&counted), 2);
+        chan.reuse();
+        assert_eq!(Rc::strong_count(&counted), 1);
+        assert!(!chan.is_ready());
+        chan.send(counted.clone());
+        assert_eq!(Rc::strong_count(&chan.recieve()), 2);
+    }
+}
@@ -0,0 +1,107 @@
+// Multi-Producer Single-Consumer Channel: any number of cloned senders push into one queue.
+// The receiver has to know when no sender is left, otherwise it would wait forever for a message
+// that can never arrive, so the senders keep count of themselves.
+
+use std::collections::VecDeque;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering::{AcqRel, Acquire, Relaxed};
+use std::sync::{Arc, Condvar, Mutex};
+
+struct Shared<T> {
+    queue: Mutex<VecDeque<T>>,
+    item_ready: Condvar,
+    senders: AtomicUsize,
+}
+
+pub struct SyncSender<T> {
+    shared: Arc<Shared<T>>,
+}
+
+pub struct Receiver<T> {
+    shared: Arc<Shared<T>>,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct Disconnected;
+
+pub fn channel_mpsc<T>() -> (SyncSender<T>, Receiver<T>) {
+    let shared = Arc::new(Shared {
+        queue: Mutex::new(VecDeque::new()),
+        item_ready: Condvar::new(),
+        senders: AtomicUsize::new(1),
+    });
+    (
+        SyncSender {
+            shared: shared.clone(),
+        },
+        Receiver { shared },
+    )
+}
+
+impl<T> SyncSender<T> {
+    pub fn send(&self, message: T) {
+        self.shared.queue.lock().unwrap().push_back(message);
+        self.shared.item_ready.notify_one();
+    }
+}
+
+impl<T> Clone for SyncSender<T> {
+    fn clone(&self) -> Self {
+        self.shared.senders.fetch_add(1, Relaxed);
+        Self {
+            shared: self.shared.clone(),
+        }
+    }
+}
+
+impl<T> Drop for SyncSender<T> {
+    fn drop(&mut self) {
+        if self.shared.senders.fetch_sub(1, AcqRel) == 1 {
+            // last sender gone. Taking the lock makes sure the receiver is either before its
+            // check of the count or already waiting, so it can't miss this notification.
+            drop(self.shared.queue.lock().unwrap());
+            self.shared.item_ready.notify_all();
+        }
+    }
+}
+
+impl<T> Receiver<T> {
+    // Err(Disconnected) only once the queue is empty, messages sent before the last sender
+    // dropped are still delivered.
+    pub fn receive(&self) -> Result<T, Disconnected> {
+        let mut queue = self.shared.queue.lock().unwrap();
+        loop {
+            if let Some(message) = queue.pop_front() {
+                return Ok(message);
+            }
+            if self.shared.senders.load(Acquire) == 0 {
+                return Err(Disconnected);
+            }
+            queue = self.shared.item_ready.wait(queue).unwrap();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    #[test]
+    fn mpsc_example() {
+        let (sender, receiver) = channel_mpsc();
+        thread::scope(|s| {
+            for i in 0..3 {
+                let sender = sender.clone();
+                s.spawn(move || sender.send(i));
+            }
+            drop(sender);
+
+            let mut received: Vec<_> = (0..3).map(|_| receiver.receive().unwrap()).collect();
+            received.sort();
+            assert_eq!(received, [0, 1, 2]);
+            // every sender is gone by now, or will be soon.
+            assert_eq!(receiver.receive(), Err(Disconnected));
+        });
+    }
+}
@@ -0,0 +1,431 @@
+// Mutex Based Channels
+//
+// Use a VecDeque,
+// send pushes  item to it
+// receive pops item from it
+
+use std::collections::VecDeque;
+use std::ops::Deref;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::Ordering::Relaxed;
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::{Duration, Instant};
+
+pub struct Channel<T> {
+    queue: Mutex<VecDeque<T>>,
+    // set by close(), only while holding the queue lock.
+    closed: AtomicBool,
+    item_ready: Condvar,
+}
+
+impl<T> Channel<T> {
+    pub fn new() -> Self {
+        Self {
+            queue: Mutex::new(VecDeque::new()),
+            closed: AtomicBool::new(false),
+            item_ready: Condvar::new(),
+        }
+    }
+
+    pub fn send(&self, message: T) {
+        self.queue.lock().unwrap().push_back(message);
+        //notify to any one waiting thread
+        self.item_ready.notify_one();
+    }
+
+    pub fn receive(&self) -> T {
+        let mut val = self.queue.lock().unwrap();
+        loop {
+            if let Some(v) = val.pop_front() {
+                return v;
+            }
+            //block the thread until you receive a notification
+            val = self.item_ready.wait(val).unwrap();
+        }
+    }
+
+    // Tells receivers that nothing more is coming. What's already queued can still be received.
+    pub fn close(&self) {
+        let queue = self.queue.lock().unwrap();
+        self.closed.store(true, Relaxed);
+        drop(queue);
+        self.item_ready.notify_all();
+    }
+
+    // Like receive, but returns None instead of waiting forever once the channel is closed and
+    // empty.
+    pub fn receive_or_closed(&self) -> Option<T> {
+        let mut queue = self.queue.lock().unwrap();
+        loop {
+            if let Some(v) = queue.pop_front() {
+                return Some(v);
+            }
+            if self.closed.load(Relaxed) {
+                return None;
+            }
+            queue = self.item_ready.wait(queue).unwrap();
+        }
+    }
+
+    // Hands every item to f as it arrives, and returns once the channel is closed and drained.
+    // close() notifies all waiters, so this wakes up for the close as well as for new items.
+    pub fn recv_all(&self, mut f: impl FnMut(T)) {
+        while let Some(v) = self.receive_or_closed() {
+            f(v);
+        }
+    }
+
+    // Queues all items under a single lock and then wakes every waiting receiver once, instead of
+    // a notify_one per item: with a batch of items there's likely work for all of them, and the
+    // ones that find the queue empty just go back to waiting.
+    pub fn send_all(&self, items: impl IntoIterator<Item = T>) {
+        // run the caller's iterator before locking: if it panics the channel isn't poisoned, and if
+        // it sends to this channel itself it doesn't deadlock.
+        let mut items: VecDeque<T> = items.into_iter().collect();
+        if items.is_empty() {
+            return;
+        }
+        self.queue.lock().unwrap().append(&mut items);
+        self.item_ready.notify_all();
+    }
+
+    // Takes everything that's queued, in order. Nothing is notified: the channel only got emptier,
+    // which no waiting receiver cares about.
+    pub fn drain(&self) -> Vec<T> {
+        std::mem::take(&mut *self.queue.lock().unwrap()).into()
+    }
+
+    pub fn clear(&self) {
+        // swap the items out first so they're dropped after the lock is released.
+        let items = std::mem::take(&mut *self.queue.lock().unwrap());
+        drop(items);
+    }
+}
+
+impl<T> Extend<T> for Channel<T> {
+    fn extend<I: IntoIterator<Item = T>>(&mut self, iter: I) {
+        self.send_all(iter);
+    }
+}
+
+impl<T> FromIterator<T> for Channel<T> {
+    fn from_iter<I: IntoIterator<Item = T>>(iter: I) -> Self {
+        Self {
+            queue: Mutex::new(iter.into_iter().collect()),
+            closed: AtomicBool::new(false),
+            item_ready: Condvar::new(),
+        }
+    }
+}
+
+impl<T> Default for Channel<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// Bounded Channel: same idea, but send blocks while `capacity` messages are waiting, so a slow
+// receiver slows the senders down instead of letting the queue grow forever. A second condition
+// variable wakes up senders when room frees up.
+//
+// A producer blocked in send borrows the channel, so the channel can't be dropped under it.
+// Shutting down goes through close() instead: every blocked and future send gets its message
+// back.
+pub struct BoundedChannel<T> {
+    queue: Mutex<VecDeque<T>>,
+    capacity: usize,
+    // only changed while holding the queue lock, so a sender can't miss it between its check and
+    // its wait.
+    closed: AtomicBool,
+    item_ready: Condvar,
+    not_full: Condvar,
+}
+
+impl<T> BoundedChannel<T> {
+    pub fn new(capacity: usize) -> Self {
+        assert!(capacity > 0, "capacity must be at least 1");
+        Self {
+            queue: Mutex::new(VecDeque::with_capacity(capacity)),
+            capacity,
+            closed: AtomicBool::new(false),
+            item_ready: Condvar::new(),
+            not_full: Condvar::new(),
+        }
+    }
+
+    pub fn send(&self, message: T) -> Result<(), T> {
+        let mut queue = self.queue.lock().unwrap();
+        loop {
+            if self.closed.load(Relaxed) {
+                return Err(message);
+            }
+            if queue.len() < self.capacity {
+                break;
+            }
+            queue = self.not_full.wait(queue).unwrap();
+        }
+        queue.push_back(message);
+        drop(queue);
+        self.item_ready.notify_one();
+        Ok(())
+    }
+
+    // Gives the message back if there was no room before the timeout. wait_timeout can wake up
+    // early, so the remaining time is recomputed from a fixed deadline every time round.
+    pub fn send_timeout(&self, message: T, timeout: Duration) -> Result<(), T> {
+        let deadline = Instant::now() + timeout;
+        let mut queue = self.queue.lock().unwrap();
+        loop {
+            if self.closed.load(Relaxed) {
+                return Err(message);
+            }
+            if queue.len() < self.capacity {
+                break;
+            }
+            let now = Instant::now();
+            if now >= deadline {
+                return Err(message);
+            }
+            queue = self.not_full.wait_timeout(queue, deadline - now).unwrap().0;
+        }
+        queue.push_back(message);
+        drop(queue);
+        self.item_ready.notify_one();
+        Ok(())
+    }
+
+    pub fn receive(&self) -> T {
+        let mut queue = self.queue.lock().unwrap();
+        loop {
+            if let Some(v) = queue.pop_front() {
+                drop(queue);
+                self.not_full.notify_one();
+                return v;
+            }
+            queue = self.item_ready.wait(queue).unwrap();
+        }
+    }
+
+    // Like receive, but returns None once the channel is closed and empty, instead of waiting for
+    // a message that can't come anymore.
+    pub fn receive_or_closed(&self) -> Option<T> {
+        let mut queue = self.queue.lock().unwrap();
+        loop {
+            if let Some(v) = queue.pop_front() {
+                drop(queue);
+                self.not_full.notify_one();
+                return Some(v);
+            }
+            if self.closed.load(Relaxed) {
+                return None;
+            }
+            queue = self.item_ready.wait(queue).unwrap();
+        }
+    }
+
+    // Wakes up everyone: blocked senders get their message back, and receivers blocked in
+    // receive_or_closed get None once the queue is empty. Plain receive keeps waiting.
+    pub fn close(&self) {
+        let queue = self.queue.lock().unwrap();
+        self.closed.store(true, Relaxed);
+        drop(queue);
+        self.not_full.notify_all();
+        self.item_ready.notify_all();
+    }
+}
+
+// An owned handle to a channel. Cloning it is as cheap as cloning the Arc, so every thread can
+// get its own and use plain thread::spawn instead of borrowing through thread::scope.
+pub struct Chan<T>(Arc<Channel<T>>);
+
+impl<T> Chan<T> {
+    pub fn new() -> Self {
+        Self(Arc::new(Channel::new()))
+    }
+}
+
+impl<T> Default for Chan<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// derive(Clone) would require T: Clone, but only the Arc gets cloned.
+impl<T> Clone for Chan<T> {
+    fn clone(&self) -> Self {
+        Self(self.0.clone())
+    }
+}
+
+impl<T> Deref for Chan<T> {
+    type Target = Channel<T>;
+
+    fn deref(&self) -> &Channel<T> {
+        &self.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn chan_example() {
+        use std::thread;
+
+        let chan = Chan::new();
+        let producers: Vec<_> = (0..3)
+            .map(|p| {
+                let chan = chan.clone();
+                thread::spawn(move || {
+                    for i in 0..10 {
+                        chan.send(p * 10 + i);
+                    }
+                })
+            })
+            .collect();
+        let consumer = thread::spawn({
+            let chan = chan.clone();
+            move || {
+                let mut received: Vec<_> = (0..30).map(|_| chan.receive()).collect();
+                received.sort();
+                received
+            }
+        });
+
+        for p in producers {
+            p.join().unwrap();
+        }
+        assert_eq!(consumer.join().unwrap(), (0..30).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn drain_example() {
+        let chan = Channel::new();
+        for i in 0..5 {
+            chan.send(i);
+        }
+        assert_eq!(chan.drain(), [0, 1, 2, 3, 4]);
+        assert!(chan.queue.lock().unwrap().is_empty());
+
+        chan.send(5);
+        chan.clear();
+        assert!(chan.drain().is_empty());
+    }
+
+    #[test]
+    fn send_timeout_example() {
+        use std::thread;
+
+        let chan = BoundedChannel::new(1);
+        chan.send(String::from("first")).unwrap();
+
+        let start = Instant::now();
+        let result = chan.send_timeout(String::from("second"), Duration::from_millis(50));
+        assert_eq!(result, Err(String::from("second")));
+        assert!(start.elapsed() >= Duration::from_millis(50));
+
+        // with a receiver making room, it goes through.
+        thread::scope(|s| {
+            s.spawn(|| {
+                thread::sleep(Duration::from_millis(20));
+                assert_eq!(chan.receive(), "first");
+            });
+            assert_eq!(
+                chan.send_timeout(String::from("third"), Duration::from_secs(10)),
+                Ok(())
+            );
+        });
+        assert_eq!(chan.receive(), "third");
+    }
+
+    #[test]
+    fn extend_example() {
+        use std::thread;
+
+        let mut chan = Channel::new();
+        chan.extend(0..10);
+        thread::scope(|s| {
+            let consumer = s.spawn(|| (0..20).map(|_| chan.receive()).collect::<Vec<_>>());
+            chan.send_all(10..20);
+            assert_eq!(consumer.join().unwrap(), (0..20).collect::<Vec<_>>());
+        });
+
+        let chan: Channel<_> = "abc".chars().collect();
+        assert_eq!(chan.drain(), ['a', 'b', 'c']);
+    }
+
+    #[test]
+    fn send_all_runs_the_iterator_unlocked() {
+        use std::panic::{self, AssertUnwindSafe};
+
+        let chan = Channel::new();
+        // sending from inside the iterator would deadlock if it ran under the lock.
+        chan.send_all((0..3).inspect(|&i| {
+            if i == 1 {
+                chan.send(10);
+            }
+        }));
+        assert_eq!(chan.drain(), [10, 0, 1, 2]);
+
+        // and a panicking iterator doesn't poison the channel.
+        let r = panic::catch_unwind(AssertUnwindSafe(|| {
+            chan.send_all((0..3).map(|i| if i == 2 { panic!("oops") } else { i }))
+        }));
+        assert!(r.is_err());
+        chan.send(5);
+        assert_eq!(chan.receive(), 5);
+    }
+
+    #[test]
+    fn close_example() {
+        use std::thread;
+
+        let chan = BoundedChannel::new(1);
+        chan.send(1).unwrap();
+        thread::scope(|s| {
+            let producer = s.spawn(|| chan.send(2));
+            thread::sleep(Duration::from_millis(20));
+            chan.close();
+            assert_eq!(producer.join().unwrap(), Err(2));
+        });
+        assert_eq!(chan.send(3), Err(3));
+        assert_eq!(chan.send_timeout(4, Duration::from_secs(1)), Err(4));
+        // what was queued before closing is still there.
+        assert_eq!(chan.receive(), 1);
+        // and then there's nothing left.
+        assert_eq!(chan.receive_or_closed(), None);
+    }
+
+    #[test]
+    fn close_wakes_blocked_receiver() {
+        use std::thread;
+
+        let chan = BoundedChannel::<i32>::new(1);
+        thread::scope(|s| {
+            let consumer = s.spawn(|| chan.receive_or_closed());
+            thread::sleep(Duration::from_millis(20));
+            chan.close();
+            assert_eq!(consumer.join().unwrap(), None);
+        });
+    }
+
+    #[test]
+    fn recv_all_example() {
+        use std::thread;
+
+        let chan = Channel::new();
+        let mut received = Vec::new();
+        thread::scope(|s| {
+            s.spawn(|| {
+                for i in 0..10 {
+                    chan.send(i);
+                    thread::sleep(Duration::from_millis(1));
+                }
+                chan.close();
+            });
+            chan.recv_all(|i| received.push(i));
+        });
+        assert_eq!(received, (0..10).collect::<Vec<_>>());
+    }
+}
@@ -0,0 +1,116 @@
+// Scoped Thread Pool
+//
+// A fixed number of workers, created inside a thread::scope, pulling jobs from a Condvar channel.
+// Since the workers are scoped threads, jobs can borrow anything that outlives the scope.
+// Dropping the pool closes the channel: the workers finish whatever is still queued, exit, and
+// the drop waits for them.
+
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering::Relaxed;
+use std::sync::Arc;
+use std::thread::{self, Scope, ScopedJoinHandle};
+
+use crate::mutex_channel::Channel;
+
+type Job<'scope> = Box<dyn FnOnce() + Send + 'scope>;
+
+pub struct ScopedPool<'scope> {
+    jobs: Arc<Channel<Job<'scope>>>,
+    workers: Vec<ScopedJoinHandle<'scope, ()>>,
+    // jobs sent but not yet picked up by a worker. Kept next to the channel rather than asking it
+    // for its length, so execute doesn't take the queue's lock a second time.
+    queued: Arc<AtomicUsize>,
+    peak_queued: AtomicUsize,
+}
+
+impl<'scope> ScopedPool<'scope> {
+    pub fn new<'env>(s: &'scope Scope<'scope, 'env>, workers: usize) -> Self {
+        let jobs: Arc<Channel<Job<'scope>>> = Arc::new(Channel::new());
+        let queued = Arc::new(AtomicUsize::new(0));
+        let workers = (0..workers.max(1))
+            .map(|_| {
+                let jobs = jobs.clone();
+                let queued = queued.clone();
+                s.spawn(move || {
+                    while let Some(job) = jobs.receive_or_closed() {
+                        queued.fetch_sub(1, Relaxed);
+                        job();
+                    }
+                })
+            })
+            .collect();
+        Self {
+            jobs,
+            workers,
+            queued,
+            peak_queued: AtomicUsize::new(0),
+        }
+    }
+
+    pub fn execute(&self, job: impl FnOnce() + Send + 'scope) {
+        // counted before sending, so a worker can't take it off the count before it's on there.
+        let depth = self.queued.fetch_add(1, Relaxed) + 1;
+        self.peak_queued.fetch_max(depth, Relaxed);
+        self.jobs.send(Box::new(job));
+    }
+
+    // The most jobs that were ever waiting for a worker at once. If it keeps growing with the
+    // number of jobs, the workers can't keep up.
+    pub fn peak_queue_depth(&self) -> usize {
+        self.peak_queued.load(Relaxed)
+    }
+}
+
+impl Drop for ScopedPool<'_> {
+    fn drop(&mut self) {
+        self.jobs.close();
+        for worker in self.workers.drain(..) {
+            // a panicking job took its worker down, pass the panic on (unless we're already
+            // unwinding, panicking twice would abort).
+            if let Err(e) = worker.join() {
+                if !thread::panicking() {
+                    std::panic::resume_unwind(e);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pool_example() {
+        use std::sync::atomic::AtomicUsize;
+        use std::sync::atomic::Ordering::Relaxed;
+
+        let count = AtomicUsize::new(0);
+        thread::scope(|s| {
+            let pool = ScopedPool::new(s, 4);
+            for _ in 0..100 {
+                pool.execute(|| {
+                    count.fetch_add(1, Relaxed);
+                });
+            }
+            drop(pool);
+            assert_eq!(count.load(Relaxed), 100);
+        });
+    }
+
+    #[test]
+    fn peak_queue_depth_example() {
+        use std::time::Duration;
+
+        thread::scope(|s| {
+            let pool = ScopedPool::new(s, 2);
+            assert_eq!(pool.peak_queue_depth(), 0);
+            for _ in 0..20 {
+                pool.execute(|| thread::sleep(Duration::from_millis(5)));
+            }
+            // two slow workers can't have taken more than a couple of jobs off the queue yet.
+            assert!(pool.peak_queue_depth() > 2);
+            assert!(pool.peak_queue_depth() <= 20);
+        });
+    }
+}
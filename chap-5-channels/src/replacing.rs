@@ -0,0 +1,149 @@
+// Replacing Channel: a oneshot-like slot for "latest value wins".
+//
+// send_replacing can be called any number of times, every call overwrites the message that's
+// waiting (if any). The receiver only ever gets the most recent one. Swapping out the old
+// message and reading it in receive both need exclusive access to the slot, so both happen under
+// a tiny internal spin lock.
+
+use std::cell::UnsafeCell;
+use std::mem::MaybeUninit;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::Ordering::{Acquire, Relaxed, Release};
+
+pub struct ReplacingChannel<T> {
+    message: UnsafeCell<MaybeUninit<T>>,
+    // only changed while holding `locked`, but is_ready peeks at it without the lock.
+    ready: AtomicBool,
+    locked: AtomicBool,
+}
+
+unsafe impl<T> Sync for ReplacingChannel<T> where T: Send {}
+
+impl<T> ReplacingChannel<T> {
+    pub const fn new() -> Self {
+        Self {
+            message: UnsafeCell::new(MaybeUninit::uninit()),
+            ready: AtomicBool::new(false),
+            locked: AtomicBool::new(false),
+        }
+    }
+
+    fn lock(&self) {
+        while self.locked.swap(true, Acquire) {
+            std::hint::spin_loop();
+        }
+    }
+
+    fn unlock(&self) {
+        self.locked.store(false, Release);
+    }
+
+    pub fn send_replacing(&self, value: T) {
+        self.lock();
+        // Safety: we hold the lock, and ready tells us whether there's a message in there.
+        let old = self
+            .ready
+            .load(Relaxed)
+            .then(|| unsafe { (*self.message.get()).assume_init_read() });
+        unsafe { (*self.message.get()).write(value) };
+        self.ready.store(true, Relaxed);
+        self.unlock();
+        // dropped outside the lock, a panicking drop must not leave it locked.
+        drop(old);
+    }
+
+    pub fn try_receive(&self) -> Option<T> {
+        self.lock();
+        // Safety: we hold the lock, and ready tells us whether there's a message in there.
+        let message = self
+            .ready
+            .swap(false, Relaxed)
+            .then(|| unsafe { (*self.message.get()).assume_init_read() });
+        self.unlock();
+        message
+    }
+
+    // spins until there's a message. A send_replacing racing us might still get its value in
+    // first, we take whatever is latest at that moment.
+    pub fn receive(&self) -> T {
+        loop {
+            if let Some(message) = self.try_receive() {
+                return message;
+            }
+            std::hint::spin_loop();
+        }
+    }
+
+    pub fn is_ready(&self) -> bool {
+        self.ready.load(Relaxed)
+    }
+}
+
+impl<T> Default for ReplacingChannel<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T> Drop for ReplacingChannel<T> {
+    fn drop(&mut self) {
+        if *self.ready.get_mut() {
+            // Safety: ready means there's a message nobody received.
+            unsafe { self.message.get_mut().assume_init_drop() }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn replacing_example() {
+        use std::sync::atomic::AtomicUsize;
+        use std::thread;
+
+        struct Counted<'a>(u32, &'a AtomicUsize);
+
+        impl Drop for Counted<'_> {
+            fn drop(&mut self) {
+                self.1.fetch_add(1, Relaxed);
+            }
+        }
+
+        let drops = AtomicUsize::new(0);
+        let chan = ReplacingChannel::new();
+        thread::scope(|s| {
+            s.spawn(|| {
+                for i in 1..=3 {
+                    chan.send_replacing(Counted(i, &drops));
+                }
+            });
+        });
+        // the first two were replaced, and dropped while being replaced.
+        assert_eq!(drops.load(Relaxed), 2);
+        assert!(chan.is_ready());
+        let last = chan.receive();
+        assert_eq!(last.0, 3);
+        assert!(!chan.is_ready());
+        assert!(chan.try_receive().is_none());
+        drop(last);
+        assert_eq!(drops.load(Relaxed), 3);
+
+        // with a receiver racing the sender, it can miss values but never goes back in time.
+        let chan = ReplacingChannel::new();
+        thread::scope(|s| {
+            s.spawn(|| {
+                for i in 1..=10_000 {
+                    chan.send_replacing(i);
+                }
+            });
+            let mut last = 0;
+            while last < 10_000 {
+                let v = chan.receive();
+                assert!(v > last);
+                last = v;
+            }
+        });
+    }
+}
@@ -0,0 +1,176 @@
+// Ring Buffer Channel: single producer, single consumer, bounded.
+//
+// The next step from the oneshot channel: instead of one slot there's a fixed array of them,
+// used round and round. `tail` counts pushed messages and is only written by the producer, `head`
+// counts popped ones and is only written by the consumer. They never wrap back to zero (well,
+// after usize::MAX messages), the slot is the counter masked with capacity - 1, and tail - head
+// is the length. That's why the capacity has to be a power of two: it divides 2^N, so the slot
+// sequence carries on unbroken when a counter wraps. With e.g. 3, counters usize::MAX and 0 would
+// both land in slot 0 while being one apart.
+// Each side publishes its counter with Release and reads the other one with Acquire, which is
+// what makes a written slot visible to the consumer and a freed slot safe to reuse for the
+// producer. No locks, no CAS loops: with exactly one thread per side there's nothing to race.
+
+use std::cell::UnsafeCell;
+use std::mem::MaybeUninit;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering::{Acquire, Relaxed, Release};
+use std::sync::Arc;
+
+struct Ring<T> {
+    slots: Box<[UnsafeCell<MaybeUninit<T>>]>,
+    head: AtomicUsize,
+    tail: AtomicUsize,
+}
+
+unsafe impl<T> Sync for Ring<T> where T: Send {}
+
+impl<T> Ring<T> {
+    fn slot(&self, counter: usize) -> usize {
+        counter & (self.slots.len() - 1)
+    }
+}
+
+impl<T> Drop for Ring<T> {
+    fn drop(&mut self) {
+        let (head, tail) = (*self.head.get_mut(), *self.tail.get_mut());
+        for n in 0..tail.wrapping_sub(head) {
+            let i = self.slot(head.wrapping_add(n));
+            // Safety: everything between head and tail was pushed and never popped.
+            unsafe { self.slots[i].get_mut().assume_init_drop() }
+        }
+    }
+}
+
+pub fn ring_channel<T>(capacity: usize) -> (Producer<T>, Consumer<T>) {
+    assert!(
+        capacity.is_power_of_two(),
+        "a ring channel's capacity must be a power of two"
+    );
+    let ring = Arc::new(Ring {
+        slots: (0..capacity)
+            .map(|_| UnsafeCell::new(MaybeUninit::uninit()))
+            .collect(),
+        head: AtomicUsize::new(0),
+        tail: AtomicUsize::new(0),
+    });
+    (Producer { ring: ring.clone() }, Consumer { ring })
+}
+
+// Neither side is Clone, that's what keeps it single producer, single consumer.
+pub struct Producer<T> {
+    ring: Arc<Ring<T>>,
+}
+
+pub struct Consumer<T> {
+    ring: Arc<Ring<T>>,
+}
+
+impl<T> Producer<T> {
+    // hands the message back if the ring is full.
+    pub fn push(&mut self, value: T) -> Result<(), T> {
+        let ring = &*self.ring;
+        // we're the only one writing tail.
+        let tail = ring.tail.load(Relaxed);
+        if tail.wrapping_sub(ring.head.load(Acquire)) == ring.slots.len() {
+            return Err(value);
+        }
+        // Safety: the slot is outside head..tail, so the consumer isn't touching it.
+        unsafe { (*ring.slots[ring.slot(tail)].get()).write(value) };
+        ring.tail.store(tail.wrapping_add(1), Release);
+        Ok(())
+    }
+}
+
+impl<T> Consumer<T> {
+    pub fn pop(&mut self) -> Option<T> {
+        let ring = &*self.ring;
+        // we're the only one writing head.
+        let head = ring.head.load(Relaxed);
+        if head == ring.tail.load(Acquire) {
+            return None;
+        }
+        // Safety: the slot is inside head..tail, so it's written and the producer won't touch it
+        // until we move head past it.
+        let value = unsafe { (*ring.slots[ring.slot(head)].get()).assume_init_read() };
+        ring.head.store(head.wrapping_add(1), Release);
+        Some(value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ring_example() {
+        use std::thread;
+
+        // full and empty.
+        let (mut p, mut c) = ring_channel(2);
+        assert_eq!(c.pop(), None);
+        assert_eq!(p.push(1), Ok(()));
+        assert_eq!(p.push(2), Ok(()));
+        assert_eq!(p.push(3), Err(3));
+        assert_eq!(c.pop(), Some(1));
+        assert_eq!(p.push(3), Ok(()));
+
+        // going around the ring a few times.
+        for i in 4..20 {
+            assert_eq!(c.pop(), Some(i - 2));
+            assert_eq!(p.push(i), Ok(()));
+        }
+        assert_eq!(c.pop(), Some(18));
+        assert_eq!(c.pop(), Some(19));
+        assert_eq!(c.pop(), None);
+
+        // messages still in the ring are dropped with it.
+        let (mut p, c) = ring_channel(4);
+        p.push(String::from("left behind")).unwrap();
+        drop((p, c));
+
+        let (mut p, mut c) = ring_channel(16);
+        thread::scope(|s| {
+            s.spawn(move || {
+                for mut i in 0..10_000 {
+                    while let Err(v) = p.push(i) {
+                        i = v;
+                        thread::yield_now();
+                    }
+                }
+            });
+            for i in 0..10_000 {
+                let v = loop {
+                    match c.pop() {
+                        Some(v) => break v,
+                        None => thread::yield_now(),
+                    }
+                };
+                assert_eq!(v, i);
+            }
+        });
+    }
+
+    #[test]
+    #[should_panic(expected = "power of two")]
+    fn ring_capacity_not_power_of_two() {
+        ring_channel::<i32>(3);
+    }
+
+    // Both counters right before wrapping around: messages still come out once each, in order.
+    #[test]
+    fn ring_counter_wrap_example() {
+        let (mut p, mut c) = ring_channel(4);
+        p.ring.head.store(usize::MAX - 1, Relaxed);
+        p.ring.tail.store(usize::MAX - 1, Relaxed);
+        for i in 0..4 {
+            assert_eq!(p.push(format!("x{i}")), Ok(()));
+        }
+        assert!(p.push(String::from("full")).is_err());
+        for i in 0..4 {
+            assert_eq!(c.pop(), Some(format!("x{i}")));
+            assert_eq!(p.push(i.to_string()), Ok(()));
+        }
+        // the rest is dropped with the ring.
+    }
+}
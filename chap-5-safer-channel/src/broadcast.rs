@@ -0,0 +1,103 @@
+// Broadcast One-Shot Channel: one message, any number of receivers.
+//
+// The message is never moved out, it stays in the channel and every receiver gets its own clone.
+// That also means a receiver subscribed after the send still sees it.
+
+use std::cell::UnsafeCell;
+use std::mem::MaybeUninit;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::Ordering::{Acquire, Release};
+use std::sync::Arc;
+
+struct Shared<T> {
+    message: UnsafeCell<MaybeUninit<T>>,
+    ready: AtomicBool,
+}
+
+// Receivers on different threads clone from a &T at the same time, so T has to be Sync too.
+unsafe impl<T> Sync for Shared<T> where T: Send + Sync {}
+
+pub struct Sender<T> {
+    shared: Arc<Shared<T>>,
+}
+
+pub struct ReceiverFactory<T> {
+    shared: Arc<Shared<T>>,
+}
+
+pub struct Receiver<T> {
+    shared: Arc<Shared<T>>,
+}
+
+pub fn broadcast<T: Clone>() -> (Sender<T>, ReceiverFactory<T>) {
+    let shared = Arc::new(Shared {
+        message: UnsafeCell::new(MaybeUninit::uninit()),
+        ready: AtomicBool::new(false),
+    });
+    (
+        Sender {
+            shared: shared.clone(),
+        },
+        ReceiverFactory { shared },
+    )
+}
+
+impl<T> Sender<T> {
+    pub fn send(self, message: T) {
+        unsafe { (*self.shared.message.get()).write(message) };
+        // publishes the message to every receiver that loads ready with Acquire.
+        self.shared.ready.store(true, Release);
+    }
+}
+
+impl<T> ReceiverFactory<T> {
+    pub fn subscribe(&self) -> Receiver<T> {
+        Receiver {
+            shared: self.shared.clone(),
+        }
+    }
+}
+
+impl<T: Clone> Receiver<T> {
+    // None until the message has been sent. Can be called any number of times.
+    pub fn recv(&self) -> Option<T> {
+        if self.shared.ready.load(Acquire) {
+            // Safety: ready is only set after the message is written, and it's never moved out.
+            Some(unsafe { (*self.shared.message.get()).assume_init_ref() }.clone())
+        } else {
+            None
+        }
+    }
+}
+
+impl<T> Drop for Shared<T> {
+    fn drop(&mut self) {
+        if *self.ready.get_mut() {
+            unsafe { self.message.get_mut().assume_init_drop() }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn broadcast_example() {
+        use std::thread;
+
+        let (sender, factory) = broadcast();
+        let early = factory.subscribe();
+        assert_eq!(early.recv(), None);
+
+        sender.send(String::from("to everyone"));
+
+        let late = factory.subscribe();
+        let third = factory.subscribe();
+        thread::scope(|s| {
+            for r in [&early, &late, &third] {
+                s.spawn(move || assert_eq!(r.recv().as_deref(), Some("to everyone")));
+            }
+        });
+    }
+}
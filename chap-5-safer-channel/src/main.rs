@@ -1,14 +1,32 @@
+// only used by its tests, main sticks to the oneshot channel.
+#[allow(unused)]
+mod broadcast;
+
 use std::sync::atomic::Ordering::{Acquire, Relaxed, Release};
 use std::{
     cell::UnsafeCell,
+    fmt,
     mem::MaybeUninit,
-    sync::{atomic::AtomicBool, Arc},
+    sync::{atomic::AtomicBool, Arc, Mutex},
+    thread::Thread,
+};
+#[cfg(feature = "async")]
+use std::{
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll, Waker},
 };
 
 pub fn channel<T>() -> (Sender<T>, Receiver<T>) {
     let a = Arc::new(Channel {
         message: UnsafeCell::new(MaybeUninit::uninit()),
         ready: AtomicBool::new(false),
+        disconnected: AtomicBool::new(false),
+        receiving_thread: Mutex::new(None),
+        sent: AtomicBool::new(false),
+        received: AtomicBool::new(false),
+        #[cfg(feature = "async")]
+        waker: Mutex::new(None),
     });
     (Sender { channel: a.clone() }, Receiver { channel: a })
 }
@@ -24,29 +42,249 @@ pub struct Receiver<T> {
 pub struct Channel<T> {
     message: UnsafeCell<MaybeUninit<T>>,
     ready: AtomicBool,
+    // set when the sender went away without sending anything, e.g. its thread panicked.
+    disconnected: AtomicBool,
+    // set while a receiver is blocked in recv(), so the sender knows whom to wake up.
+    receiving_thread: Mutex<Option<Thread>>,
+    // ready can't tell "never sent" from "already received", these can. Sender's Drop relies on
+    // sent, received is only for stats().
+    sent: AtomicBool,
+    received: AtomicBool,
+    // the task awaiting the Receiver, woken the same way as receiving_thread.
+    #[cfg(feature = "async")]
+    waker: Mutex<Option<Waker>>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ChannelStats {
+    pub sent: bool,
+    pub received: bool,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct Disconnected;
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum RecvError {
+    Disconnected,
+    Cancelled,
+}
+
+// A shared "please stop waiting" flag. Cancelling only sets the flag: a thread that is parked has
+// to be unparked by whoever cancels, so it gets to look at it.
+#[derive(Clone, Default)]
+pub struct CancellationToken(Arc<AtomicBool>);
+
+impl CancellationToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn cancel(&self) {
+        self.0.store(true, Release);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Acquire)
+    }
+}
+
+// The message may not be initialized (or may be getting written right now), so the Debug output
+// only shows the state of the channel and never needs T: Debug.
+impl<T> fmt::Debug for Sender<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Sender")
+            .field("ready", &self.channel.ready.load(Relaxed))
+            .field("strong_count", &Arc::strong_count(&self.channel))
+            .finish()
+    }
+}
+
+impl<T> fmt::Debug for Receiver<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Receiver")
+            .field("ready", &self.channel.ready.load(Relaxed))
+            .field("strong_count", &Arc::strong_count(&self.channel))
+            .finish()
+    }
 }
 
 unsafe impl<T> Sync for Channel<T> where T: Send {}
 
+impl<T> Channel<T> {
+    // Relaxed is fine, these are just for looking at, e.g. from a benchmark that times a send.
+    pub fn stats(&self) -> ChannelStats {
+        ChannelStats {
+            sent: self.sent.load(Relaxed),
+            received: self.received.load(Relaxed),
+        }
+    }
+
+    // No message is going to show up anymore: the sender went away without sending, or the
+    // message has already been taken.
+    fn is_finished(&self) -> bool {
+        self.disconnected.load(Relaxed) || self.received.load(Relaxed)
+    }
+
+    // Safety: the caller must have just swapped ready from true to false.
+    unsafe fn take_message(&self) -> T {
+        self.received.store(true, Relaxed);
+        unsafe { (*self.message.get()).assume_init_read() }
+    }
+}
+
 impl<T> Sender<T> {
+    // Only the sender and the receiver hold the Arc, so a count of one means the receiver is gone
+    // and producing the message would be wasted work. Like any check, it can go stale right away.
+    pub fn is_connected(&self) -> bool {
+        Arc::strong_count(&self.channel) > 1
+    }
+
     pub fn send(self, message: T) {
         unsafe { (*self.channel.message.get()).write(message) };
+        self.channel.sent.store(true, Relaxed);
         self.channel.ready.store(true, Release);
     }
+
+    // for when the caller wants to keep its own copy.
+    pub fn send_ref(self, message: &T)
+    where
+        T: Clone,
+    {
+        self.send(message.clone());
+    }
+}
+
+// send() consumes the sender, so this also runs right after a successful send. ready can't tell
+// us whether that happened: try_receive may already have taken the message and cleared it. sent
+// can, and it was set by this very thread, so Relaxed is enough.
+impl<T> Drop for Sender<T> {
+    fn drop(&mut self) {
+        if !self.channel.sent.load(Relaxed) {
+            self.channel.disconnected.store(true, Release);
+        }
+        // either way there's news for a receiver blocked in recv().
+        if let Some(t) = &*self.channel.receiving_thread.lock().unwrap() {
+            t.unpark();
+        }
+        // or awaiting it.
+        #[cfg(feature = "async")]
+        if let Some(waker) = self.channel.waker.lock().unwrap().take() {
+            waker.wake();
+        }
+    }
 }
 
 impl<T> Receiver<T> {
+    // also true once the sender is gone, so a polling loop wakes up and receive() can report it.
+    // Relaxed is enough: the message is only ever read through receive(), which acquires itself.
+    // A true result doesn't make anything the sender did visible on its own, but unlike the
+    // oneshot in chap-5-channels there's no peek here that would need it to.
     pub fn is_ready(&self) -> bool {
-        self.channel.ready.load(Relaxed)
+        self.channel.ready.load(Relaxed) || self.channel.disconnected.load(Relaxed)
+    }
+    pub fn receive(self) -> Result<T, Disconnected> {
+        if self.channel.ready.swap(false, Acquire) {
+            return Ok(unsafe { self.channel.take_message() });
+        }
+        if self.channel.disconnected.load(Acquire) {
+            return Err(Disconnected);
+        }
+        panic!("No Messages yet!");
+    }
+
+    pub fn stats(&self) -> ChannelStats {
+        self.channel.stats()
+    }
+
+    // Blocks until the message arrives, the sender is dropped, or the token is cancelled.
+    pub fn recv(self, token: &CancellationToken) -> Result<T, RecvError> {
+        // registered before checking anything, so a send in between still unparks us.
+        *self.channel.receiving_thread.lock().unwrap() = Some(thread::current());
+        loop {
+            if self.channel.ready.swap(false, Acquire) {
+                return Ok(unsafe { self.channel.take_message() });
+            }
+            if self.channel.disconnected.load(Acquire) {
+                return Err(RecvError::Disconnected);
+            }
+            if token.is_cancelled() {
+                return Err(RecvError::Cancelled);
+            }
+            thread::park();
+        }
+    }
+
+    // receive through a shared reference, the swap makes sure the message is only taken once.
+    pub fn try_receive(&self) -> Option<T> {
+        if self.channel.ready.swap(false, Acquire) {
+            Some(unsafe { self.channel.take_message() })
+        } else {
+            None
+        }
     }
-    pub fn receive(self) -> T {
-        if !self.channel.ready.swap(false, Acquire) {
-            panic!("No Messages yet!");
+}
+
+// Awaiting a Receiver is the async version of recv(): the task is woken when the sender sends or
+// goes away. Sender's Drop does the waking, it runs right after send() as well.
+#[cfg(feature = "async")]
+impl<T> Future for Receiver<T> {
+    type Output = Result<T, Disconnected>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let channel = &self.channel;
+        // registering takes the lock, so only do it when the message isn't there yet. Checking
+        // once more afterwards catches a sender that finished in between.
+        for register in [true, false] {
+            if channel.ready.swap(false, Acquire) {
+                return Poll::Ready(Ok(unsafe { channel.take_message() }));
+            }
+            if channel.disconnected.load(Acquire) {
+                return Poll::Ready(Err(Disconnected));
+            }
+            if register {
+                *channel.waker.lock().unwrap() = Some(cx.waker().clone());
+            }
         }
-        unsafe { (*self.channel.message.get()).assume_init_read() }
+        Poll::Pending
     }
 }
 
+#[derive(Debug, PartialEq, Eq)]
+pub enum Either<A, B> {
+    Left(A),
+    Right(B),
+}
+
+// Waits for whichever of the two messages arrives first. Like recv(), the selecting thread is
+// registered with both channels, so either sender unparks it when it sends or goes away.
+//
+// Panics if neither channel can deliver a message anymore: their senders were dropped without
+// sending, or the message was already taken with try_receive.
+pub fn select2<A, B>(a: &Receiver<A>, b: &Receiver<B>) -> Either<A, B> {
+    // registered before checking anything, so a send in between still unparks us.
+    *a.channel.receiving_thread.lock().unwrap() = Some(thread::current());
+    *b.channel.receiving_thread.lock().unwrap() = Some(thread::current());
+    let result = loop {
+        if let Some(message) = a.try_receive() {
+            break Either::Left(message);
+        }
+        if let Some(message) = b.try_receive() {
+            break Either::Right(message);
+        }
+        if a.channel.is_finished() && b.channel.is_finished() {
+            // nobody to unpark us anymore.
+            *a.channel.receiving_thread.lock().unwrap() = None;
+            *b.channel.receiving_thread.lock().unwrap() = None;
+            panic!("neither channel can deliver a message anymore");
+        }
+        thread::park();
+    };
+    *a.channel.receiving_thread.lock().unwrap() = None;
+    *b.channel.receiving_thread.lock().unwrap() = None;
+    result
+}
+
 impl<T> Drop for Channel<T> {
     fn drop(&mut self) {
         if *self.ready.get_mut() {
@@ -67,6 +305,225 @@ fn main() {
         while !receiver.is_ready() {
             thread::park();
         }
-        assert_eq!(receiver.receive(), "57471");
+        assert_eq!(receiver.receive(), Ok("57471"));
     });
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn disconnected_example() {
+        let (sender, receiver) = channel::<String>();
+        drop(sender);
+        assert!(receiver.is_ready());
+        assert_eq!(receiver.receive(), Err(Disconnected));
+    }
+
+    #[test]
+    fn select2_example() {
+        let (slow_sender, slow) = channel();
+        let (fast_sender, fast) = channel();
+        thread::scope(|s| {
+            s.spawn(move || {
+                thread::sleep(Duration::from_millis(200));
+                slow_sender.send("slow");
+            });
+            s.spawn(move || {
+                thread::sleep(Duration::from_millis(20));
+                fast_sender.send(7);
+            });
+            assert_eq!(select2(&slow, &fast), Either::Right(7));
+        });
+        assert_eq!(slow.receive(), Ok("slow"));
+    }
+
+    // One message already taken, the other sender gone without sending: nothing can arrive.
+    #[test]
+    #[should_panic(expected = "neither channel can deliver a message anymore")]
+    fn select2_nothing_left() {
+        let (a_sender, a) = channel();
+        let (b_sender, b) = channel::<u32>();
+        a_sender.send("taken");
+        assert_eq!(a.try_receive(), Some("taken"));
+        thread::scope(|s| {
+            s.spawn(move || {
+                thread::sleep(Duration::from_millis(20));
+                drop(b_sender);
+            });
+            select2(&a, &b);
+        });
+    }
+
+    // a receiver polling with try_receive can take the message between send() storing it and the
+    // sender being dropped. That must not count as the sender going away.
+    #[test]
+    fn try_receive_before_sender_drop() {
+        let (sender, receiver) = channel();
+        // what send() does, minus the drop at the end.
+        unsafe { (*sender.channel.message.get()).write(1) };
+        sender.channel.sent.store(true, Relaxed);
+        sender.channel.ready.store(true, Release);
+        assert_eq!(receiver.try_receive(), Some(1));
+        drop(sender);
+        assert!(!receiver.is_ready());
+        assert!(!receiver.channel.disconnected.load(Relaxed));
+    }
+
+    #[test]
+    fn debug_example() {
+        // no Debug for the payload
+        struct Opaque;
+
+        let (sender, receiver) = channel();
+        assert!(format!("{sender:?}").contains("ready: false"));
+        assert_eq!(
+            format!("{receiver:?}"),
+            "Receiver { ready: false, strong_count: 2 }"
+        );
+        sender.send(Opaque);
+        assert!(format!("{receiver:?}").contains("ready: true"));
+    }
+
+    #[test]
+    fn is_connected_example() {
+        let (sender, receiver) = channel::<u32>();
+        assert!(sender.is_connected());
+        drop(receiver);
+        assert!(!sender.is_connected());
+    }
+
+    #[test]
+    fn cancellation_example() {
+        let (sender, receiver) = channel::<u32>();
+        let token = CancellationToken::new();
+        thread::scope(|s| {
+            let t = s.spawn(|| receiver.recv(&token));
+            thread::sleep(Duration::from_millis(20));
+            token.cancel();
+            t.thread().unpark();
+            assert_eq!(t.join().unwrap(), Err(RecvError::Cancelled));
+        });
+        // the sender was alive the whole time
+        drop(sender);
+
+        let (sender, receiver) = channel();
+        thread::scope(|s| {
+            let t = s.spawn(|| receiver.recv(&CancellationToken::new()));
+            thread::sleep(Duration::from_millis(20));
+            sender.send(5);
+            assert_eq!(t.join().unwrap(), Ok(5));
+        });
+    }
+
+    #[test]
+    fn send_ref_example() {
+        let (sender, receiver) = channel();
+        let original = String::from("still mine");
+        sender.send_ref(&original);
+        let received = receiver.receive().unwrap();
+        assert_eq!(received, original);
+        assert_ne!(received.as_ptr(), original.as_ptr());
+    }
+
+    #[test]
+    fn stats_example() {
+        let (sender, receiver) = channel();
+        assert_eq!(
+            receiver.stats(),
+            ChannelStats {
+                sent: false,
+                received: false
+            }
+        );
+        sender.send(1);
+        // sent, but nobody took it yet.
+        assert_eq!(
+            receiver.stats(),
+            ChannelStats {
+                sent: true,
+                received: false
+            }
+        );
+        assert_eq!(receiver.try_receive(), Some(1));
+        assert_eq!(
+            receiver.stats(),
+            ChannelStats {
+                sent: true,
+                received: true
+            }
+        );
+    }
+
+    // Times a message from send to receive, nothing to check:
+    // cargo test --release -- --ignored --nocapture oneshot_latency
+    #[test]
+    #[ignore]
+    fn oneshot_latency() {
+        use std::time::Instant;
+
+        let (sender, receiver) = channel();
+        thread::scope(|s| {
+            s.spawn(move || sender.send(Instant::now()));
+            let start = loop {
+                if let Some(start) = receiver.try_receive() {
+                    break start;
+                }
+                std::hint::spin_loop();
+            };
+            println!("oneshot latency: {:?}", start.elapsed());
+        });
+    }
+
+    // Just enough of an executor to drive one future: park the thread until the waker unparks it.
+    #[cfg(feature = "async")]
+    fn block_on<F: Future>(future: F) -> F::Output {
+        use std::task::Wake;
+
+        struct Unparker(Thread);
+
+        impl Wake for Unparker {
+            fn wake(self: Arc<Self>) {
+                self.0.unpark();
+            }
+        }
+
+        let waker = Waker::from(Arc::new(Unparker(thread::current())));
+        let mut cx = Context::from_waker(&waker);
+        let mut future = std::pin::pin!(future);
+        loop {
+            match future.as_mut().poll(&mut cx) {
+                Poll::Ready(output) => return output,
+                Poll::Pending => thread::park(),
+            }
+        }
+    }
+
+    #[cfg(feature = "async")]
+    #[test]
+    fn future_example() {
+        let (sender, receiver) = channel();
+        thread::scope(|s| {
+            s.spawn(move || {
+                thread::sleep(Duration::from_millis(20));
+                sender.send(String::from("awaited"));
+            });
+            let len = block_on(async {
+                let message = receiver.await?;
+                Ok::<_, Disconnected>(message.len())
+            });
+            assert_eq!(len, Ok(7));
+        });
+
+        let (sender, receiver) = channel::<u32>();
+        thread::scope(|s| {
+            s.spawn(move || {
+                thread::sleep(Duration::from_millis(20));
+                drop(sender);
+            });
+            assert_eq!(block_on(receiver), Err(Disconnected));
+        });
+    }
+}
@@ -1,47 +1,116 @@
+use std::cell::Cell;
 use std::marker::PhantomData;
 use std::sync::atomic::Ordering::{Acquire, Relaxed, Release};
-use std::thread;
+use std::thread::{self, Thread};
 use std::{cell::UnsafeCell, mem::MaybeUninit, sync::atomic::AtomicBool};
+
+// Parker: thread::park/unpark plus a flag that remembers the wakeup. An unpark that comes before
+// the park sets the flag, and park returns right away instead of sleeping. Spurious wakeups of
+// thread::park don't count, park only returns once the flag was really set.
+pub struct Parker {
+    woken: AtomicBool,
+    thread: Thread,
+}
+
+impl Parker {
+    // parks the thread that creates it, only that thread may call park().
+    pub fn new() -> Self {
+        Self {
+            woken: AtomicBool::new(false),
+            thread: thread::current(),
+        }
+    }
+
+    pub fn park(&self) {
+        debug_assert_eq!(thread::current().id(), self.thread.id());
+        while !self.woken.swap(false, Acquire) {
+            thread::park();
+        }
+    }
+
+    pub fn unpark(&self) {
+        self.woken.store(true, Release);
+        self.thread.unpark();
+    }
+}
+
+impl Default for Parker {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 pub struct Channel<T> {
     message: UnsafeCell<MaybeUninit<T>>,
     ready: AtomicBool,
+    // set up by split, for the thread calling it.
+    parker: Option<Parker>,
 }
 
 unsafe impl<T> Sync for Channel<T> where T: Send {}
 
 pub struct Sender<'a, T> {
     channel: &'a Channel<T>,
-    receiving_thead: Thread,
+    parker: &'a Parker,
 }
 
 pub struct Receiver<'a, T> {
     channel: &'a Channel<T>,
+    parker: &'a Parker,
+    _no_send: PhantomData<*const ()>,
+}
+
+#[derive(Clone, Copy)]
+pub struct ReadinessHandle<'a> {
+    ready: &'a AtomicBool,
     _no_send: PhantomData<*const ()>,
 }
 
+impl ReadinessHandle<'_> {
+    // Relaxed like Receiver::is_ready, the handle can't get at the message at all.
+    pub fn is_ready(&self) -> bool {
+        self.ready.load(Relaxed)
+    }
+}
+
 impl<T> Channel<T> {
     pub const fn new() -> Self {
         Self {
             message: UnsafeCell::new(MaybeUninit::uninit()),
             ready: AtomicBool::new(false),
+            parker: None,
         }
     }
 
+    // Can be called again once the previous message has been received, which reuses the same
+    // buffer, so two threads can ping-pong over it.
     pub fn split<'a>(&'a mut self) -> (Sender<'a, T>, Receiver<'a, T>) {
+        debug_assert!(
+            !*self.ready.get_mut(),
+            "split a channel that still holds a message"
+        );
+        // in release builds a pending message gets dropped here instead of leaking.
         *self = Self::new();
+        self.parker = Some(Parker::new());
+        let channel = &*self;
+        let parker = channel.parker.as_ref().unwrap();
         (
-            Sender {
-                channel: self,
-                receiving_thead: thread::current(),
-            },
+            Sender { channel, parker },
             Receiver {
-                channel: self,
+                channel,
+                parker,
                 _no_send: PhantomData,
             },
         )
     }
 }
 
+impl<T> Default for Channel<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl<T> Drop for Channel<T> {
     fn drop(&mut self) {
         if *self.ready.get_mut() {
@@ -51,23 +120,122 @@ impl<T> Drop for Channel<T> {
 }
 
 impl<T> Sender<'_, T> {
-    pub fn send(self, message: T) {
+    pub fn send(mut self, message: T) {
+        if self.try_send(message).is_err() {
+            panic!("the previous message hasn't been received yet");
+        }
+    }
+
+    // Keeps the sender, so the same buffer can carry one message after another without splitting again.
+    // A message that's still pending is left alone and the new one is handed back.
+    pub fn try_send(&mut self, message: T) -> Result<(), T> {
+        // Acquire pairs with the Release store in the receiver, which happens after it's done
+        // reading. Seeing false means the slot is really free to overwrite.
+        if self.channel.ready.load(Acquire) {
+            return Err(message);
+        }
         unsafe { (*self.channel.message.get()).write(message) };
         self.channel.ready.store(true, Release);
-        self.receiving_thead.unpark();
+        self.parker.unpark();
+        Ok(())
     }
 }
 
-impl<T> Receiver<'_, T> {
+impl<'a, T> Receiver<'a, T> {
+    // Only a hint, nothing is read based on it. receive's own Acquire load syncs with send, and
+    // there's no peek that would read the message right after a true result, so no need for an
+    // Acquire variant.
     pub fn is_ready(&self) -> bool {
         self.channel.ready.load(Relaxed)
     }
 
+    // A copyable handle that can only check readiness, e.g. for polling in one place and
+    // receiving in another. Like the receiver itself it stays on this thread.
+    pub fn readiness_handle(&self) -> ReadinessHandle<'a> {
+        ReadinessHandle {
+            ready: &self.channel.ready,
+            _no_send: PhantomData,
+        }
+    }
+
     pub fn receive(self) -> T {
-        if !self.channel.ready.swap(false, Acquire) {
-            thread::park();
+        // The parker only wakes us for a send, but looping costs nothing.
+        while !self.channel.ready.load(Acquire) {
+            self.parker.park();
+        }
+        unsafe { self.take_message() }
+    }
+
+    // Polls without giving up the receiver. A message is only ever read once, a second call after
+    // taking it just returns None.
+    pub fn try_receive(&mut self) -> Option<T> {
+        if self.channel.ready.load(Acquire) {
+            Some(unsafe { self.take_message() })
+        } else {
+            None
+        }
+    }
+
+    // Safety: ready must be true. There's only one receiver, so nobody can take it in between.
+    unsafe fn take_message(&self) -> T {
+        let message = unsafe { (*self.channel.message.get()).assume_init_read() };
+        // cleared only after reading: once try_send sees false it may overwrite the slot right
+        // away. Clearing it at all keeps the message from being dropped a second time.
+        self.channel.ready.store(false, Release);
+        message
+    }
+}
+
+// LocalChannel: the same oneshot for a single thread, e.g. tasks taking turns on one thread. It's
+// neither Send nor Sync (Cell and UnsafeCell see to that), so T doesn't have to be Send either,
+// Rc and friends are fine. Nothing is shared between threads, so a plain Cell<bool> does the job
+// of the atomic flag and there's nothing to block on: receive on an empty channel is a bug.
+pub struct LocalChannel<T> {
+    message: UnsafeCell<MaybeUninit<T>>,
+    ready: Cell<bool>,
+}
+
+impl<T> LocalChannel<T> {
+    pub const fn new() -> Self {
+        Self {
+            message: UnsafeCell::new(MaybeUninit::uninit()),
+            ready: Cell::new(false),
+        }
+    }
+
+    pub fn send(&self, message: T) {
+        if self.ready.get() {
+            panic!("LocalChannel still holds a message");
+        }
+        // Safety: not ready, so the slot is empty and no one else can be using it on this thread.
+        unsafe { (*self.message.get()).write(message) };
+        self.ready.set(true);
+    }
+
+    pub fn is_ready(&self) -> bool {
+        self.ready.get()
+    }
+
+    pub fn receive(&self) -> T {
+        if !self.ready.replace(false) {
+            panic!("no message available");
+        }
+        // Safety: it was ready, and clearing the flag first means it's only read once.
+        unsafe { (*self.message.get()).assume_init_read() }
+    }
+}
+
+impl<T> Default for LocalChannel<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T> Drop for LocalChannel<T> {
+    fn drop(&mut self) {
+        if self.ready.get() {
+            unsafe { self.message.get_mut().assume_init_drop() }
         }
-        unsafe { (*self.channel.message.get()).assume_init_read() }
     }
 }
 
@@ -81,3 +249,139 @@ fn main() {
         assert_eq!(receiver.receive(), "hello, world");
     })
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn split_again_example() {
+        let mut channel = Channel::new();
+        for i in 0..3 {
+            thread::scope(|s| {
+                let (sender, receiver) = channel.split();
+                s.spawn(move || sender.send(i));
+                assert_eq!(receiver.receive(), i);
+            });
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "still holds a message")]
+    #[cfg(debug_assertions)]
+    fn split_with_pending_message() {
+        let mut channel = Channel::new();
+        let (sender, _receiver) = channel.split();
+        sender.send(1);
+        channel.split();
+    }
+
+    #[test]
+    fn try_receive_example() {
+        let mut channel = Channel::new();
+        let (sender, mut receiver) = channel.split();
+        assert_eq!(receiver.try_receive(), None);
+        sender.send(String::from("polled"));
+        assert_eq!(receiver.try_receive().as_deref(), Some("polled"));
+        assert_eq!(receiver.try_receive(), None);
+    }
+
+    #[test]
+    fn readiness_handle_example() {
+        let mut channel = Channel::new();
+        let (sender, receiver) = channel.split();
+        let handle = receiver.readiness_handle();
+        let copy = handle;
+        assert!(!handle.is_ready());
+        sender.send(1);
+        assert!(handle.is_ready() && copy.is_ready());
+        assert_eq!(receiver.receive(), 1);
+    }
+
+    #[test]
+    fn parker_example() {
+        use std::time::{Duration, Instant};
+
+        // unpark before park: the wakeup is remembered.
+        let parker = Parker::new();
+        parker.unpark();
+        let start = Instant::now();
+        parker.park();
+        assert!(start.elapsed() < Duration::from_secs(1));
+
+        // the message and its wakeup arrive before the receiver even looks.
+        let mut channel = Channel::new();
+        thread::scope(|s| {
+            let (sender, receiver) = channel.split();
+            s.spawn(move || sender.send(1)).join().unwrap();
+            let start = Instant::now();
+            assert_eq!(receiver.receive(), 1);
+            assert!(start.elapsed() < Duration::from_secs(1));
+        });
+
+        // and the other way around, with the receiver already parked.
+        thread::scope(|s| {
+            let (sender, receiver) = channel.split();
+            s.spawn(move || {
+                thread::sleep(Duration::from_millis(20));
+                sender.send(2);
+            });
+            assert_eq!(receiver.receive(), 2);
+        });
+    }
+
+    #[test]
+    fn local_channel_example() {
+        use std::cell::RefCell;
+        use std::rc::Rc;
+
+        let shared = Rc::new(RefCell::new(vec![1, 2]));
+        let channel = LocalChannel::new();
+        assert!(!channel.is_ready());
+        channel.send(shared.clone());
+        assert!(channel.is_ready());
+
+        let received = channel.receive();
+        received.borrow_mut().push(3);
+        assert_eq!(*shared.borrow(), [1, 2, 3]);
+
+        // and back again, the channel can be used once more after receiving.
+        channel.send(received);
+        drop(channel);
+        // dropping the unreceived message gave the Rc back.
+        assert_eq!(Rc::strong_count(&shared), 1);
+    }
+
+    #[test]
+    fn try_send_example() {
+        let mut channel = Channel::new();
+        thread::scope(|s| {
+            let (mut sender, mut receiver) = channel.split();
+            assert_eq!(sender.try_send(1), Ok(()));
+            // the first one hasn't been picked up yet.
+            assert_eq!(sender.try_send(2), Err(2));
+            assert_eq!(receiver.try_receive(), Some(1));
+            assert_eq!(sender.try_send(2), Ok(()));
+            assert_eq!(receiver.try_receive(), Some(2));
+
+            // and from another thread, one message after the other over the same buffer.
+            s.spawn(move || {
+                for mut i in 3..100 {
+                    while let Err(v) = sender.try_send(i) {
+                        i = v;
+                        thread::yield_now();
+                    }
+                }
+            });
+            for i in 3..100 {
+                let v = loop {
+                    match receiver.try_receive() {
+                        Some(v) => break v,
+                        None => thread::yield_now(),
+                    }
+                };
+                assert_eq!(v, i);
+            }
+        });
+    }
+}
@@ -0,0 +1,167 @@
+// Spin-then-park Lock
+//
+// Spinning is great when the lock is only held for a moment, and a waste of a whole core when
+// it's held for long. HybridLock spins a little like SpinLock, and if that didn't get it the lock,
+// it puts the thread on a list of waiters and parks it. Unlocking wakes one waiter, which then
+// tries again (and goes back on the list if someone else was quicker).
+//
+// The list sits behind a Mutex, but `waiting` counts its entries so that unlocking a lock nobody
+// is parked on never has to touch it. Checking the count right after unlocking is the store
+// buffer pattern from chapter 3 (store one flag, load the other) with the waiter doing the mirror
+// image, so both sides use SeqCst: otherwise the unlocker could see no waiters while the waiter
+// still sees the lock as taken, and the waiter would sleep forever.
+
+use std::cell::UnsafeCell;
+use std::ops::{Deref, DerefMut};
+use std::sync::atomic::Ordering::{Acquire, Relaxed, SeqCst};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize};
+use std::sync::Mutex;
+use std::thread::{self, Thread};
+
+// how many failed attempts before giving up on spinning.
+const SPINS_BEFORE_PARKING: u32 = 100;
+
+pub struct HybridLock<T> {
+    locked: AtomicBool,
+    value: UnsafeCell<T>,
+    waiters: Mutex<Vec<Thread>>,
+    // the length of waiters, readable without locking it.
+    waiting: AtomicUsize,
+    spins: AtomicU64,
+}
+
+unsafe impl<T> Sync for HybridLock<T> where T: Send {}
+
+impl<T> HybridLock<T> {
+    pub const fn new(value: T) -> Self {
+        Self {
+            locked: AtomicBool::new(false),
+            value: UnsafeCell::new(value),
+            waiters: Mutex::new(Vec::new()),
+            waiting: AtomicUsize::new(0),
+            spins: AtomicU64::new(0),
+        }
+    }
+
+    pub fn lock(&self) -> HybridGuard<'_, T> {
+        for _ in 0..SPINS_BEFORE_PARKING {
+            if !self.locked.swap(true, Acquire) {
+                return HybridGuard { lock: self };
+            }
+            self.spins.fetch_add(1, Relaxed);
+            std::hint::spin_loop();
+        }
+        loop {
+            // get on the list before the last check, an unlock in between will see us there.
+            self.register();
+            if !self.locked.swap(true, SeqCst) {
+                self.unregister();
+                return HybridGuard { lock: self };
+            }
+            // woken by an unlock, which took us off the list, or spuriously. Either way: register
+            // again (unregistering first, in case we're still on there) and have another go.
+            thread::park();
+            self.unregister();
+        }
+    }
+
+    fn register(&self) {
+        let mut waiters = self.waiters.lock().unwrap();
+        waiters.push(thread::current());
+        self.waiting.fetch_add(1, SeqCst);
+    }
+
+    fn unregister(&self) {
+        let me = thread::current().id();
+        let mut waiters = self.waiters.lock().unwrap();
+        if let Some(i) = waiters.iter().position(|t| t.id() == me) {
+            waiters.swap_remove(i);
+            self.waiting.fetch_sub(1, Relaxed);
+        }
+    }
+
+    fn unlock(&self) {
+        self.locked.store(false, SeqCst);
+        if self.waiting.load(SeqCst) == 0 {
+            return;
+        }
+        let mut waiters = self.waiters.lock().unwrap();
+        if let Some(t) = waiters.pop() {
+            self.waiting.fetch_sub(1, Relaxed);
+            drop(waiters);
+            t.unpark();
+        }
+    }
+
+    // how many times lock() spun in total. Stays bounded however long the lock is held, since
+    // each lock() call spins at most SPINS_BEFORE_PARKING times.
+    pub fn spin_count(&self) -> u64 {
+        self.spins.load(Relaxed)
+    }
+}
+
+pub struct HybridGuard<'a, T> {
+    lock: &'a HybridLock<T>,
+}
+
+// Same as TicketGuard: sharing the guard shares &T, so T has to be Sync for that.
+unsafe impl<T> Sync for HybridGuard<'_, T> where T: Sync {}
+
+impl<T> Deref for HybridGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        // Safety: the existence of this guard guarantees we've exclusively locked the lock.
+        unsafe { &*self.lock.value.get() }
+    }
+}
+
+impl<T> DerefMut for HybridGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        // Safety: the existence of this guard guarantees we've exclusively locked the lock.
+        unsafe { &mut *self.lock.value.get() }
+    }
+}
+
+impl<T> Drop for HybridGuard<'_, T> {
+    fn drop(&mut self) {
+        self.lock.unlock();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The holder sleeps with the lock for a while. Spinning waiters would burn through millions of
+    // spins in that time, parked ones stop after SPINS_BEFORE_PARKING each.
+    #[test]
+    fn long_hold_example() {
+        use std::time::Duration;
+
+        let lock = HybridLock::new(0);
+        thread::scope(|s| {
+            let g = lock.lock();
+            for _ in 0..4 {
+                s.spawn(|| *lock.lock() += 1);
+            }
+            thread::sleep(Duration::from_millis(100));
+            drop(g);
+        });
+        assert_eq!(*lock.lock(), 4);
+        assert!(lock.spin_count() <= 4 * SPINS_BEFORE_PARKING as u64);
+
+        // and it's still a lock under plain contention.
+        let lock = HybridLock::new(0);
+        thread::scope(|s| {
+            for _ in 0..8 {
+                s.spawn(|| {
+                    for _ in 0..10_000 {
+                        *lock.lock() += 1;
+                    }
+                });
+            }
+        });
+        assert_eq!(*lock.lock(), 80_000);
+    }
+}
@@ -0,0 +1,212 @@
+// Reader-Writer Spin Lock
+
+// A reader-writer lock allows either any number of readers or a single writer at a time.
+// The whole state fits in one AtomicU32:
+//   0        -> unlocked
+//   u32::MAX -> locked by a writer
+//   n        -> locked by n readers
+
+use std::cell::UnsafeCell;
+use std::ops::{Deref, DerefMut};
+use std::sync::atomic::AtomicU32;
+use std::sync::atomic::Ordering::{Acquire, Relaxed, Release};
+
+const WRITE_LOCKED: u32 = u32::MAX;
+
+pub struct SpinRwLock<T> {
+    state: AtomicU32,
+    value: UnsafeCell<T>,
+}
+
+// Readers on different threads all get a &T at the same time, so T needs to be Sync as well.
+unsafe impl<T> Sync for SpinRwLock<T> where T: Send + Sync {}
+
+impl<T> SpinRwLock<T> {
+    pub const fn new(value: T) -> Self {
+        Self {
+            state: AtomicU32::new(0),
+            value: UnsafeCell::new(value),
+        }
+    }
+
+    pub fn read(&self) -> ReadGuard<'_, T> {
+        let mut s = self.state.load(Relaxed);
+        loop {
+            // u32::MAX - 1 readers would make the next one look like a writer.
+            if s < WRITE_LOCKED - 1 {
+                match self.state.compare_exchange_weak(s, s + 1, Acquire, Relaxed) {
+                    Ok(_) => return ReadGuard { rwlock: self },
+                    Err(e) => s = e,
+                }
+            } else {
+                std::hint::spin_loop();
+                s = self.state.load(Relaxed);
+            }
+        }
+    }
+
+    pub fn write(&self) -> WriteGuard<'_, T> {
+        while self
+            .state
+            .compare_exchange_weak(0, WRITE_LOCKED, Acquire, Relaxed)
+            .is_err()
+        {
+            std::hint::spin_loop();
+        }
+        WriteGuard { rwlock: self }
+    }
+}
+
+pub struct ReadGuard<'a, T> {
+    rwlock: &'a SpinRwLock<T>,
+}
+
+impl<'a, T> ReadGuard<'a, T> {
+    // Only possible if we're the sole reader: 1 -> writer in one step, so no other writer can get
+    // in between. With other readers around the guard is handed back unchanged.
+    pub fn try_upgrade(guard: Self) -> Result<WriteGuard<'a, T>, Self> {
+        let rwlock = guard.rwlock;
+        // Acquire, so the other readers that have left are really done with the value.
+        match rwlock
+            .state
+            .compare_exchange(1, WRITE_LOCKED, Acquire, Relaxed)
+        {
+            Ok(_) => {
+                // the write guard takes over our read lock, don't decrement it.
+                std::mem::forget(guard);
+                Ok(WriteGuard { rwlock })
+            }
+            Err(_) => Err(guard),
+        }
+    }
+}
+
+impl<T> Deref for ReadGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        // Safety: while a read guard exists no writer can lock, so shared access is fine.
+        unsafe { &*self.rwlock.value.get() }
+    }
+}
+
+impl<T> Drop for ReadGuard<'_, T> {
+    fn drop(&mut self) {
+        self.rwlock.state.fetch_sub(1, Release);
+    }
+}
+
+pub struct WriteGuard<'a, T> {
+    rwlock: &'a SpinRwLock<T>,
+}
+
+impl<'a, T> WriteGuard<'a, T> {
+    // Turns the exclusive lock into a shared one without ever unlocking, so no other writer can
+    // sneak in. Nobody else can touch the state while we're the writer, a plain store will do.
+    pub fn downgrade(guard: Self) -> ReadGuard<'a, T> {
+        let rwlock = guard.rwlock;
+        std::mem::forget(guard);
+        // Release, so readers coming in after this see what we wrote.
+        rwlock.state.store(1, Release);
+        ReadGuard { rwlock }
+    }
+}
+
+impl<T> Deref for WriteGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        // Safety: the existence of this guard guarantees we've exclusively locked the lock.
+        unsafe { &*self.rwlock.value.get() }
+    }
+}
+
+impl<T> DerefMut for WriteGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        // Safety: the existence of this guard guarantees we've exclusively locked the lock.
+        unsafe { &mut *self.rwlock.value.get() }
+    }
+}
+
+impl<T> Drop for WriteGuard<'_, T> {
+    fn drop(&mut self) {
+        self.rwlock.state.store(0, Release);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Readers keep checking that the two halves of the pair are equal while a writer bumps both,
+    // a torn update would be visible to them as a mismatch.
+    #[test]
+    fn readers_and_writer() {
+        use std::thread;
+
+        let lock = SpinRwLock::new((0u64, 0u64));
+        thread::scope(|s| {
+            for _ in 0..4 {
+                s.spawn(|| {
+                    for _ in 0..10_000 {
+                        let g = lock.read();
+                        assert_eq!(g.0, g.1);
+                    }
+                });
+            }
+            s.spawn(|| {
+                for _ in 0..10_000 {
+                    let mut g = lock.write();
+                    g.0 += 1;
+                    g.1 += 1;
+                }
+            });
+        });
+        assert_eq!(*lock.read(), (10_000, 10_000));
+    }
+
+    #[test]
+    fn upgrade_and_downgrade() {
+        use std::thread;
+
+        let lock = SpinRwLock::new(0);
+
+        let mut w = lock.write();
+        *w += 1;
+        let r = WriteGuard::downgrade(w);
+        assert_eq!(*r, 1);
+        // other readers are welcome now, writers still aren't.
+        assert_eq!(*lock.read(), 1);
+        assert_eq!(lock.state.load(Relaxed), 1);
+
+        // the sole reader can upgrade.
+        let mut w =
+            ReadGuard::try_upgrade(r).unwrap_or_else(|_| panic!("sole reader must upgrade"));
+        *w += 1;
+        drop(w);
+        assert_eq!(lock.state.load(Relaxed), 0);
+
+        // with a second reader it can't, and both read locks are still there.
+        let r1 = lock.read();
+        let r2 = lock.read();
+        let r1 = match ReadGuard::try_upgrade(r1) {
+            Ok(_) => panic!("upgraded next to another reader"),
+            Err(r1) => r1,
+        };
+        assert_eq!(lock.state.load(Relaxed), 2);
+        drop(r2);
+        let w = ReadGuard::try_upgrade(r1).unwrap_or_else(|_| panic!("sole reader must upgrade"));
+
+        // a downgraded writer keeps the lock the whole time: the writer thread only gets in after
+        // our read guard is gone.
+        let r = WriteGuard::downgrade(w);
+        thread::scope(|s| {
+            let writer = s.spawn(|| *lock.write() = 10);
+            thread::sleep(std::time::Duration::from_millis(10));
+            assert_eq!(*r, 2);
+            drop(r);
+            writer.join().unwrap();
+        });
+        assert_eq!(*lock.read(), 10);
+    }
+}
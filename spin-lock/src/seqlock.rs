@@ -0,0 +1,110 @@
+// Sequence Lock
+//
+// For small, read-mostly data that's cheap to copy. Readers never write to shared memory at all,
+// they copy the value out and then check whether a writer got in the way:
+//   - the sequence number is even while nobody writes and odd while a writer is busy.
+//   - a writer bumps it to odd, writes the data, and bumps it to even again.
+//   - a reader reads the sequence, copies the data, and reads the sequence again. If it was odd,
+//     or changed in between, the copy might be torn and the reader simply tries again.
+//
+// That's why T must be Copy: a reader can end up holding a torn copy for a moment, which is only
+// harmless if T is plain bits with no Drop and no invariants to break. Those torn reads are also
+// why the value lives in an UnsafeCell and is copied with read_volatile: strictly speaking they're
+// still a data race in Rust's memory model, the volatile copy just keeps the compiler from
+// assuming anything about it. Crates like seqlock work the same way.
+
+use std::cell::UnsafeCell;
+use std::sync::atomic::Ordering::{Acquire, Relaxed, Release};
+use std::sync::atomic::{fence, AtomicU64};
+
+pub struct SeqLock<T> {
+    seq: AtomicU64,
+    value: UnsafeCell<T>,
+}
+
+// Readers on other threads copy the value out, writers move new ones in.
+unsafe impl<T> Sync for SeqLock<T> where T: Copy + Send {}
+
+impl<T: Copy> SeqLock<T> {
+    pub const fn new(value: T) -> Self {
+        Self {
+            seq: AtomicU64::new(0),
+            value: UnsafeCell::new(value),
+        }
+    }
+
+    pub fn read(&self) -> T {
+        loop {
+            let s1 = self.seq.load(Acquire);
+            if s1 % 2 == 1 {
+                // a writer is busy, no point copying.
+                std::hint::spin_loop();
+                continue;
+            }
+            // Safety: might race with a writer, but T is Copy and a torn copy is thrown away below.
+            let value = unsafe { std::ptr::read_volatile(self.value.get()) };
+            // keeps the copy above from moving after the second load.
+            fence(Acquire);
+            if self.seq.load(Relaxed) == s1 {
+                return value;
+            }
+        }
+    }
+
+    // Writers exclude each other by being the one to make the sequence odd.
+    pub fn write(&self, value: T) {
+        let mut s = self.seq.load(Relaxed);
+        loop {
+            if s % 2 == 1 {
+                std::hint::spin_loop();
+                s = self.seq.load(Relaxed);
+                continue;
+            }
+            match self.seq.compare_exchange_weak(s, s + 1, Acquire, Relaxed) {
+                Ok(_) => break,
+                Err(e) => s = e,
+            }
+        }
+        // a reader that sees any of the data written below must also see the odd sequence.
+        fence(Release);
+        // Safety: we're the only writer, readers only copy and will notice the changed sequence.
+        unsafe { std::ptr::write_volatile(self.value.get(), value) };
+        self.seq.store(s + 2, Release);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // One writer keeps the two halves equal, a torn read would show up as a mismatch.
+    #[test]
+    fn no_torn_reads() {
+        use std::sync::atomic::AtomicBool;
+        use std::thread;
+
+        let lock = SeqLock::new((0u64, 0u64));
+        let done = AtomicBool::new(false);
+        thread::scope(|s| {
+            for _ in 0..4 {
+                s.spawn(|| {
+                    let mut last = 0;
+                    while !done.load(Relaxed) {
+                        let (a, b) = lock.read();
+                        assert_eq!(a, b);
+                        // and a reader never sees an older value after a newer one.
+                        assert!(a >= last);
+                        last = a;
+                    }
+                });
+            }
+            s.spawn(|| {
+                for i in 1..=100_000 {
+                    lock.write((i, i));
+                }
+                done.store(true, Relaxed);
+            });
+        });
+        assert_eq!(lock.read(), (100_000, 100_000));
+    }
+}
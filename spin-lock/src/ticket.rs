@@ -0,0 +1,96 @@
+// Ticket Spin Lock
+
+// SpinLock hands the lock to whichever thread happens to win the swap, so under contention an
+// unlucky thread can lose over and over. A ticket lock works like the queue at a deli counter:
+// every thread takes a number and waits until that number is served, so threads get the lock in
+// the order they asked for it.
+
+use std::cell::UnsafeCell;
+use std::ops::{Deref, DerefMut};
+use std::sync::atomic::AtomicU32;
+use std::sync::atomic::Ordering::{Acquire, Relaxed, Release};
+
+pub struct TicketSpinLock<T> {
+    next_ticket: AtomicU32,
+    now_serving: AtomicU32,
+    value: UnsafeCell<T>,
+}
+
+unsafe impl<T> Sync for TicketSpinLock<T> where T: Send {}
+
+impl<T> TicketSpinLock<T> {
+    pub const fn new(value: T) -> Self {
+        Self {
+            next_ticket: AtomicU32::new(0),
+            now_serving: AtomicU32::new(0),
+            value: UnsafeCell::new(value),
+        }
+    }
+
+    pub fn lock(&self) -> TicketGuard<'_, T> {
+        // tickets wrap around, that's fine as long as fewer than 2^32 threads wait at once.
+        let ticket = self.next_ticket.fetch_add(1, Relaxed);
+        while self.now_serving.load(Acquire) != ticket {
+            std::hint::spin_loop();
+        }
+        TicketGuard { lock: self, ticket }
+    }
+}
+
+pub struct TicketGuard<'a, T> {
+    lock: &'a TicketSpinLock<T>,
+    ticket: u32,
+}
+
+// Sharing a guard shares &T. The automatic Sync would only ask for T: Send (through the lock),
+// which would let a Cell be used from two threads at once.
+unsafe impl<T> Sync for TicketGuard<'_, T> where T: Sync {}
+
+impl<T> Deref for TicketGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        // Safety: only the holder of the ticket being served can have a guard.
+        unsafe { &*self.lock.value.get() }
+    }
+}
+
+impl<T> DerefMut for TicketGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        // Safety: only the holder of the ticket being served can have a guard.
+        unsafe { &mut *self.lock.value.get() }
+    }
+}
+
+impl<T> Drop for TicketGuard<'_, T> {
+    fn drop(&mut self) {
+        // next please
+        self.lock.now_serving.fetch_add(1, Release);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Every critical section writes down the ticket it was served with. If the lock is fair, that
+    // list is just all tickets in order, and every thread got its turns.
+    #[test]
+    fn ticket_order() {
+        use std::thread;
+
+        let lock = TicketSpinLock::new(Vec::new());
+        thread::scope(|s| {
+            for _ in 0..8 {
+                s.spawn(|| {
+                    for _ in 0..100 {
+                        let mut g = lock.lock();
+                        let ticket = g.ticket;
+                        g.push(ticket);
+                    }
+                });
+            }
+        });
+        assert_eq!(*lock.lock(), (0..800).collect::<Vec<_>>());
+    }
+}